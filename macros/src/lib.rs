@@ -0,0 +1,94 @@
+//! # wascc-actor-macros
+//!
+//! Procedural macros for the `wascc-actor` crate. These are re-exported by `wascc-actor`
+//! and should not be depended upon directly.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, ItemFn, Path, Token};
+
+struct HandlerArgs {
+    operation: Path,
+    raw: bool,
+}
+
+impl Parse for HandlerArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let operation: Path = input.parse()?;
+        let mut raw = false;
+        if input.peek(Token![,]) {
+            input.parse::<Token![,]>()?;
+            let flag: Ident = input.parse()?;
+            if flag != "raw" {
+                return Err(syn::Error::new(
+                    flag.span(),
+                    "expected `raw` or no handler option",
+                ));
+            }
+            raw = true;
+        }
+        Ok(HandlerArgs { operation, raw })
+    }
+}
+
+/// Marks a function as the handler for an operation. A handler receives the decoded
+/// request type and returns a `HandlerResult` of any serializable response type. With the
+/// `raw` option, the handler instead receives the raw payload as `&[u8]` and returns the
+/// raw response bytes. Handlers are collected into the actor's dispatcher by listing their
+/// paths in `actor_routes!`.
+#[proc_macro_attribute]
+pub fn handler(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as HandlerArgs);
+    let func = parse_macro_input!(item as ItemFn);
+
+    let name = &func.sig.ident;
+    let vis = &func.vis;
+    let operation = &args.operation;
+    let actor = actor_crate();
+    let invoke = if args.raw {
+        quote! { super::#name(msg).map_err(|e| e.into()) }
+    } else {
+        quote! {
+            let response = super::#name(#actor::prelude::deserialize(msg)?)?;
+            #actor::prelude::serialize(response)
+        }
+    };
+
+    let expanded = quote! {
+        #func
+
+        #[doc(hidden)]
+        #vis mod #name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            pub const ROUTE: #actor::dispatch::Route = #actor::dispatch::Route {
+                operation: #operation,
+                handler: handle,
+            };
+
+            fn handle(msg: &[u8]) -> #actor::HandlerResult<Vec<u8>> {
+                #invoke
+            }
+        }
+    };
+    expanded.into()
+}
+
+/// The path through which generated code refers to `wascc-actor`, honouring a rename of
+/// the dependency in the actor's manifest. The crate's own tests and doctests see it as
+/// `wascc_actor`, as does any actor whose manifest can't be read.
+fn actor_crate() -> proc_macro2::TokenStream {
+    match crate_name("wascc-actor") {
+        Ok(FoundCrate::Name(name)) => {
+            let name = Ident::new(&name, Span::call_site());
+            quote! { ::#name }
+        }
+        Ok(FoundCrate::Itself) | Err(_) => quote! { ::wascc_actor },
+    }
+}
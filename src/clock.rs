@@ -0,0 +1,82 @@
+//! # Clock
+//!
+//! waSCC actors have no direct access to the system clock, yet several of the key-value
+//! abstractions (rate limiters, leases, queues and caches) need to know the current time.
+//! This module provides the current time, in whole seconds since the Unix epoch, from a
+//! replaceable time source. When compiled natively the system clock is used unless another
+//! source is installed; in a WebAssembly module a source must be installed before the time
+//! is needed, for example one that reads a timestamp supplied with the incoming request.
+//! Like the host transport, the time source is installed per thread.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::testing::MockClock;
+//!
+//! let clock = MockClock::new(1_600_000_000);
+//! clock::set_time_source(clock.clone());
+//! clock.advance(5);
+//! assert_eq!(clock::now()?, 1_600_000_005);
+//!
+//! clock::set_time_source(|| 42);
+//! assert_eq!(clock::now()?, 42);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use crate::errors::{self, ErrorKind, Result};
+use std::cell::RefCell;
+
+/// A source of the current time, in seconds since the Unix epoch
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+impl<F> TimeSource for F
+where
+    F: Fn() -> u64,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+thread_local! {
+    static SOURCE: RefCell<Option<Box<dyn TimeSource>>> = const { RefCell::new(None) };
+}
+
+/// Installs the time source used by the current thread, returning the previous one
+pub fn set_time_source<T>(source: T) -> Option<Box<dyn TimeSource>>
+where
+    T: TimeSource + 'static,
+{
+    SOURCE.with(|s| s.replace(Some(Box::new(source))))
+}
+
+/// Removes the time source installed on the current thread, reverting to the default
+pub fn clear_time_source() -> Option<Box<dyn TimeSource>> {
+    SOURCE.with(|s| s.replace(None))
+}
+
+/// Returns the current time in seconds since the Unix epoch
+pub fn now() -> Result<u64> {
+    SOURCE.with(|s| match *s.borrow() {
+        Some(ref source) => Ok(source.now()),
+        None => default_now(),
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_now() -> Result<u64> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .map_err(|e| errors::new(ErrorKind::MiscError(Box::new(e))))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_now() -> Result<u64> {
+    Err(errors::new(ErrorKind::MiscError(
+        "No time source installed; see clock::set_time_source".into(),
+    )))
+}
@@ -0,0 +1,141 @@
+//! # Request Context
+//!
+//! While an operation is being dispatched by `actor_handlers!`, an ambient `Context`
+//! describes the current invocation: the operation name, the size of the raw payload and
+//! an invocation ID that is unique within this actor instance. The context also carries a
+//! scratch map that middleware can use to pass values (such as an authenticated principal
+//! or a trace ID) to handlers without resorting to globals. The context is discarded when
+//! dispatch completes.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::middleware::Middleware;
+//!
+//! struct Principal;
+//!
+//! impl Middleware for Principal {
+//!     fn before(&self, _operation: &str, _msg: &[u8]) -> HandlerResult<Option<Vec<u8>>> {
+//!         context::set("principal", "alice");
+//!         Ok(None)
+//!     }
+//! }
+//!
+//! actor_handlers!{
+//!     codec::core::OP_HEALTH_REQUEST => health;
+//!     middleware [Principal]
+//! }
+//!
+//! fn health(_req: codec::core::HealthRequest) -> HandlerResult<()> {
+//!     let ctx = context::current().unwrap();
+//!     assert_eq!(ctx.operation(), codec::core::OP_HEALTH_REQUEST);
+//!     assert_eq!(ctx.get("principal"), Some("alice"));
+//!     Ok(())
+//! }
+//! # let harness = actor::testing::harness::ActorHarness::new(handle_wapc);
+//! # harness.invoke::<_, ()>(codec::core::OP_HEALTH_REQUEST, codec::core::HealthRequest { placeholder: true }).unwrap();
+//! # assert!(context::current().is_none());
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+/// Describes the operation currently being handled by the actor
+#[derive(Debug, Clone)]
+pub struct Context {
+    operation: String,
+    payload_size: usize,
+    invocation_id: u64,
+    values: HashMap<String, String>,
+}
+
+impl Context {
+    /// The name of the operation being dispatched
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// The size, in bytes, of the raw payload delivered with the operation
+    pub fn payload_size(&self) -> usize {
+        self.payload_size
+    }
+
+    /// An identifier for this invocation, unique within the actor instance
+    pub fn invocation_id(&self) -> u64 {
+        self.invocation_id
+    }
+
+    /// Retrieves a value previously stored in the context's scratch map
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|v| v.as_str())
+    }
+
+    /// Returns all values stored in the context's scratch map
+    pub fn values(&self) -> &HashMap<String, String> {
+        &self.values
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Context>> = const { RefCell::new(None) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(1) };
+}
+
+/// Returns a snapshot of the context for the operation currently being dispatched, or
+/// `None` when called outside of dispatch
+pub fn current() -> Option<Context> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+/// Stores a value in the current context's scratch map, replacing any previous value for
+/// the key. This has no effect when called outside of dispatch.
+pub fn set(key: &str, value: &str) {
+    CURRENT.with(|c| {
+        if let Some(ref mut ctx) = *c.borrow_mut() {
+            ctx.values.insert(key.to_string(), value.to_string());
+        }
+    })
+}
+
+/// Retrieves a value from the current context's scratch map
+pub fn get(key: &str) -> Option<String> {
+    CURRENT.with(|c| {
+        c.borrow()
+            .as_ref()
+            .and_then(|ctx| ctx.values.get(key).cloned())
+    })
+}
+
+/// Establishes the context for a single dispatch, restoring the previous context when
+/// dropped. This is created by the dispatcher generated by `actor_handlers!`.
+#[doc(hidden)]
+pub struct Scope {
+    previous: Option<Context>,
+}
+
+impl Scope {
+    pub fn begin(operation: &str, payload_size: usize) -> Scope {
+        let invocation_id = NEXT_ID.with(|id| {
+            let next = id.get();
+            id.set(next.wrapping_add(1));
+            next
+        });
+        let ctx = Context {
+            operation: operation.to_string(),
+            payload_size,
+            invocation_id,
+            values: HashMap::new(),
+        };
+        Scope {
+            previous: CURRENT.with(|c| c.replace(Some(ctx))),
+        }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|c| c.replace(previous));
+    }
+}
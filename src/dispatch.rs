@@ -0,0 +1,73 @@
+//! # Dispatch
+//!
+//! Support for the dispatchers generated by `actor_handlers!` and `actor_routes!`. Actor
+//! developers do not normally need anything in this module directly; it is public so that
+//! the code generated by the macros (including the `#[handler]` attribute) can reach it.
+
+use crate::{context, logger, middleware, HandlerResult};
+
+/// A single operation handler collected by `actor_routes!`. One of these is generated
+/// for every function annotated with `#[handler]`.
+#[derive(Clone, Copy)]
+pub struct Route {
+    pub operation: &'static str,
+    pub handler: fn(&[u8]) -> HandlerResult<Vec<u8>>,
+}
+
+/// Performs the work common to every dispatch: installing the logger and panic hook,
+/// establishing the request context, and running the middleware chain around the handler
+#[doc(hidden)]
+pub fn dispatch<F>(operation: &str, msg: &[u8], handler: F) -> HandlerResult<Vec<u8>>
+where
+    F: FnOnce(&str, &[u8]) -> HandlerResult<Vec<u8>>,
+{
+    logger::ensure_logger();
+    let _context = context::Scope::begin(operation, msg.len());
+    middleware::run(operation, msg, handler)
+}
+
+/// Invokes the route registered for the operation, or the fallback if there is none
+#[doc(hidden)]
+pub fn route(
+    routes: &[Route],
+    operation: &str,
+    msg: &[u8],
+    fallback: fn(&str, &[u8]) -> HandlerResult<Vec<u8>>,
+) -> HandlerResult<Vec<u8>> {
+    match routes.iter().find(|r| r.operation == operation) {
+        Some(r) => (r.handler)(msg),
+        None => fallback(operation, msg),
+    }
+}
+
+/// Fails compilation (when evaluated in a constant) if any operation appears in more
+/// than one route
+#[doc(hidden)]
+pub const fn assert_unique(routes: &[Route]) {
+    let mut i = 0;
+    while i < routes.len() {
+        let mut j = i + 1;
+        while j < routes.len() {
+            if str_eq(routes[i].operation, routes[j].operation) {
+                panic!("an operation is registered to more than one handler in actor_routes!");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
@@ -22,21 +22,104 @@ use std::fmt;
 #[derive(Debug)]
 pub struct Error(Box<ErrorKind>);
 
+/// The result type returned by the capability host bindings
+pub type Result<T> = std::result::Result<T, Error>;
+
 pub(crate) fn new(kind: ErrorKind) -> Error {
     Error(Box::new(kind))
 }
 
+/// Identifies the capability provider operation that failed, the binding it was invoked
+/// on, and the message reported by the provider (or the reason its response was unusable)
+///
+/// # Example
+/// ```
+/// # use wascc_actor as actor;
+/// use actor::prelude::*;
+/// use actor::errors::ErrorKind;
+///
+/// struct Garbage;
+///
+/// impl transport::HostTransport for Garbage {
+///     fn host_call(&self, _: &str, _: &str, _: &str, _: &[u8]) -> HandlerResult<Vec<u8>> {
+///         Ok(vec![0xc1])
+///     }
+/// }
+///
+/// transport::set_transport(Garbage);
+/// let err = keyvalue::default().get("key").unwrap_err();
+/// match err.kind() {
+///     ErrorKind::ResponseDecode(e) => assert_eq!(e.operation, codec::keyvalue::OP_GET),
+///     _ => panic!("expected a decode error"),
+/// }
+///
+/// transport::clear_transport();
+/// let err = keyvalue::default().get("key").unwrap_err();
+/// assert!(matches!(err.kind(), ErrorKind::KeyValueError(_)));
+/// assert_eq!(err.provider().unwrap().binding, "default");
+/// ```
+#[derive(Debug)]
+pub struct ProviderError {
+    pub capid: String,
+    pub binding: String,
+    pub operation: String,
+    pub message: String,
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) on binding '{}': {}",
+            self.capid, self.operation, self.binding, self.message
+        )
+    }
+}
+
+/// Creates an error of the given kind for a failed host call
+pub(crate) fn provider_error(
+    kind: fn(ProviderError) -> ErrorKind,
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    source: Box<dyn StdError + Send + Sync>,
+) -> Error {
+    new(kind(ProviderError {
+        capid: capid.to_string(),
+        binding: binding.to_string(),
+        operation: operation.to_string(),
+        message: source.to_string(),
+    }))
+}
+
+/// Creates an error indicating that the response to a host call could not be decoded
+pub(crate) fn decode_error(
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    source: Box<dyn StdError + Send + Sync>,
+) -> Error {
+    provider_error(ErrorKind::ResponseDecode, binding, capid, operation, source)
+}
+
 #[derive(Debug)]
 pub enum ErrorKind {
-    KeyValueError(String),
-    MessagingError(String),
-    MiscError(Box<dyn ::std::error::Error>),
+    KeyValueError(ProviderError),
+    MessagingError(ProviderError),
+    ObjectStoreError(ProviderError),
+    EventStreamError(ProviderError),
+    HttpClientError(ProviderError),
+    ExtrasError(ProviderError),
+    MiscError(Box<dyn ::std::error::Error + Send + Sync>),
     EnvVar(std::env::VarError),
     UTF8(std::string::FromUtf8Error),
     UTF8Str(std::str::Utf8Error),
     JsonMarshaling(serde_json::Error),
-    HostError(String),
+    Base64(base64::DecodeError),
+    HostError(ProviderError),
     BadDispatch(String),
+    Conflict(String),
+    ResponseDecode(ProviderError),
     WapcError(wapc::errors::Error),
 }
 
@@ -48,6 +131,22 @@ impl Error {
     pub fn into_kind(self) -> ErrorKind {
         *self.0
     }
+
+    /// Returns the details of the failed capability provider call, if this error was
+    /// produced by a host binding
+    pub fn provider(&self) -> Option<&ProviderError> {
+        match *self.0 {
+            ErrorKind::KeyValueError(ref e)
+            | ErrorKind::MessagingError(ref e)
+            | ErrorKind::ObjectStoreError(ref e)
+            | ErrorKind::EventStreamError(ref e)
+            | ErrorKind::HttpClientError(ref e)
+            | ErrorKind::ExtrasError(ref e)
+            | ErrorKind::HostError(ref e)
+            | ErrorKind::ResponseDecode(ref e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl StdError for Error {
@@ -56,28 +155,42 @@ impl StdError for Error {
             ErrorKind::KeyValueError(_) => "Key/value store error",
             ErrorKind::UTF8(_) => "UTF8 encoding failure",
             ErrorKind::MessagingError(_) => "Messaging error",
+            ErrorKind::ObjectStoreError(_) => "Object store error",
+            ErrorKind::EventStreamError(_) => "Event stream error",
+            ErrorKind::HttpClientError(_) => "HTTP client error",
+            ErrorKind::ExtrasError(_) => "Extras error",
             ErrorKind::EnvVar(_) => "Environment variable error",
             ErrorKind::JsonMarshaling(_) => "JSON encoding/decoding failure",
+            ErrorKind::Base64(_) => "Base64 decoding failure",
             ErrorKind::UTF8Str(_) => "UTF8 encoding failure",
             ErrorKind::HostError(_) => "Host Error",
             ErrorKind::BadDispatch(_) => "Bad dispatch",
+            ErrorKind::Conflict(_) => "Version conflict",
+            ErrorKind::ResponseDecode(_) => "Provider response decoding failure",
             ErrorKind::WapcError(_) => "waPC failure",
             ErrorKind::MiscError(_) => "Misc error",
         }
     }
 
-    fn cause(&self) -> Option<&dyn StdError> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
         match *self.0 {
             ErrorKind::KeyValueError(_) => None,
             ErrorKind::UTF8(ref e) => Some(e),
             ErrorKind::MessagingError(_) => None,
+            ErrorKind::ObjectStoreError(_) => None,
+            ErrorKind::EventStreamError(_) => None,
+            ErrorKind::HttpClientError(_) => None,
+            ErrorKind::ExtrasError(_) => None,
             ErrorKind::EnvVar(ref e) => Some(e),
             ErrorKind::JsonMarshaling(ref e) => Some(e),
+            ErrorKind::Base64(ref e) => Some(e),
             ErrorKind::UTF8Str(ref e) => Some(e),
             ErrorKind::HostError(_) => None,
             ErrorKind::BadDispatch(_) => None,
+            ErrorKind::Conflict(_) => None,
+            ErrorKind::ResponseDecode(_) => None,
             ErrorKind::WapcError(ref e) => Some(e),
-            ErrorKind::MiscError(_) => None,
+            ErrorKind::MiscError(ref e) => Some(e.as_ref()),
         }
     }
 }
@@ -88,11 +201,20 @@ impl fmt::Display for Error {
             ErrorKind::KeyValueError(ref msg) => write!(f, "Key/Value error: {}", msg),
             ErrorKind::UTF8(ref e) => write!(f, "UTF8 encoding error: {}", e),
             ErrorKind::MessagingError(ref msg) => write!(f, "Messaging error: {}", msg),
+            ErrorKind::ObjectStoreError(ref e) => write!(f, "Object store error: {}", e),
+            ErrorKind::EventStreamError(ref e) => write!(f, "Event stream error: {}", e),
+            ErrorKind::HttpClientError(ref e) => write!(f, "HTTP client error: {}", e),
+            ErrorKind::ExtrasError(ref e) => write!(f, "Extras error: {}", e),
             ErrorKind::EnvVar(ref e) => write!(f, "Environment variable error: {}", e),
             ErrorKind::JsonMarshaling(ref e) => write!(f, "JSON marshaling error: {}", e),
+            ErrorKind::Base64(ref e) => write!(f, "Base64 decoding error: {}", e),
             ErrorKind::UTF8Str(ref e) => write!(f, "UTF8 error: {}", e),
             ErrorKind::HostError(ref e) => write!(f, "Host error: {}", e),
             ErrorKind::BadDispatch(ref e) => write!(f, "Bad dispatch, attempted operation: {}", e),
+            ErrorKind::Conflict(ref key) => write!(f, "Version conflict updating key '{}'", key),
+            ErrorKind::ResponseDecode(ref e) => {
+                write!(f, "Failed to decode provider response from {}", e)
+            }
             ErrorKind::WapcError(ref e) => write!(f, "waPC error: {}", e),
             ErrorKind::MiscError(ref e) => write!(f, "Misc error: {}", e),
         }
@@ -117,6 +239,12 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<base64::DecodeError> for Error {
+    fn from(source: base64::DecodeError) -> Error {
+        Error(Box::new(ErrorKind::Base64(source)))
+    }
+}
+
 impl From<std::env::VarError> for Error {
     fn from(source: std::env::VarError) -> Error {
         Error(Box::new(ErrorKind::EnvVar(source)))
@@ -137,6 +265,82 @@ impl From<Box<dyn ::std::error::Error + Send + Sync>> for Error {
 
 impl From<Box<dyn ::std::error::Error>> for Error {
     fn from(source: Box<dyn ::std::error::Error>) -> Error {
-        Error(Box::new(ErrorKind::MiscError(source)))
+        Error(Box::new(ErrorKind::MiscError(Box::new(
+            DetachedError::new(source.as_ref()),
+        ))))
+    }
+}
+
+/// A copy of an error that isn't `Send + Sync`, preserving the message of every error in
+/// its `source` chain
+#[derive(Debug)]
+struct DetachedError {
+    message: String,
+    source: Option<Box<DetachedError>>,
+}
+
+impl DetachedError {
+    fn new(error: &dyn StdError) -> DetachedError {
+        DetachedError {
+            message: error.to_string(),
+            source: error.source().map(|e| Box::new(DetachedError::new(e))),
+        }
+    }
+}
+
+impl fmt::Display for DetachedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for DetachedError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_ref()
+            .map(|e| e.as_ref() as &(dyn StdError + 'static))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Outer(std::io::Error);
+
+    impl fmt::Display for Outer {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("outer")
+        }
+    }
+
+    impl StdError for Outer {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    fn outer() -> Outer {
+        Outer(std::io::Error::other("inner"))
+    }
+
+    #[test]
+    fn misc_error_is_its_source() {
+        let boxed: Box<dyn StdError + Send + Sync> = Box::new(outer());
+        let err = Error::from(boxed);
+        let source = err.source().unwrap();
+        assert!(source.downcast_ref::<Outer>().is_some());
+        assert_eq!(source.source().unwrap().to_string(), "inner");
+    }
+
+    #[test]
+    fn unsendable_error_keeps_its_source_chain() {
+        let boxed: Box<dyn StdError> = Box::new(outer());
+        let err = Error::from(boxed);
+        let source = err.source().unwrap();
+        assert_eq!(source.to_string(), "outer");
+        assert_eq!(source.source().unwrap().to_string(), "inner");
+        assert!(source.source().unwrap().source().is_none());
     }
 }
@@ -1,9 +1,10 @@
+use crate::transport;
 use std::collections::HashMap;
-use wapc_guest::host_call;
 use wascc_codec::eventstreams::*;
-use wascc_codec::{deserialize, serialize};
 
-use crate::HandlerResult;
+use crate::errors::{ErrorKind, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 const CAPID_EVENTS: &str = "wascc:eventstreams";
 
@@ -28,54 +29,33 @@ pub struct EventStreamsHostBinding {
 
 impl EventStreamsHostBinding {
     /// Writes the given event (a collection of key-value pairs) to a named stream
-    pub fn write_event(
-        &self,
-        stream: &str,
-        values: HashMap<String, String>,
-    ) -> HandlerResult<String> {
+    pub fn write_event(&self, stream: &str, values: HashMap<String, String>) -> Result<String> {
         let ev = Event {
             event_id: "".to_string(),
             stream: stream.to_string(),
             values,
         };
 
-        host_call(&self.binding, CAPID_EVENTS, OP_WRITE_EVENT, &serialize(ev)?)
-            .map(|v| {
-                deserialize::<WriteResponse>(&v)
-                    .unwrap()
-                    .event_id
-                    .to_string()
-            })
-            .map_err(|e| e.into())
+        self.call(OP_WRITE_EVENT, ev)
+            .map(|r: WriteResponse| r.event_id)
     }
 
     /// Reads all available events from the given stream
-    pub fn read_all(&self, stream: &str) -> HandlerResult<Vec<Event>> {
+    pub fn read_all(&self, stream: &str) -> Result<Vec<Event>> {
         let query = self.generate_query(0, stream, None);
         self.execute_query(query)
     }
 
     /// Reads all available events from a given stream up to a given maximum number.
     /// May return less than the specified limit if less than that exist on the stream
-    pub fn read_limit(&self, stream: &str, limit: u64) -> HandlerResult<Vec<Event>> {
+    pub fn read_limit(&self, stream: &str, limit: u64) -> Result<Vec<Event>> {
         let query = self.generate_query(limit, stream, None);
         self.execute_query(query)
     }
 
-    fn execute_query(&self, query: StreamQuery) -> HandlerResult<Vec<Event>> {
-        host_call(
-            &self.binding,
-            CAPID_EVENTS,
-            OP_QUERY_STREAM,
-            &serialize(query)?,
-        )
-        .map(|v| {
-            deserialize::<StreamResults>(v.as_ref())
-                .unwrap()
-                .events
-                .clone()
-        })
-        .map_err(|e| e.into())
+    fn execute_query(&self, query: StreamQuery) -> Result<Vec<Event>> {
+        self.call(OP_QUERY_STREAM, query)
+            .map(|r: StreamResults| r.events)
     }
 
     fn generate_query(&self, count: u64, stream: &str, range: Option<TimeRange>) -> StreamQuery {
@@ -85,4 +65,14 @@ impl EventStreamsHostBinding {
             range,
         }
     }
+
+    fn call<T: DeserializeOwned>(&self, operation: &str, cmd: impl Serialize) -> Result<T> {
+        transport::call(
+            ErrorKind::EventStreamError,
+            &self.binding,
+            CAPID_EVENTS,
+            operation,
+            cmd,
+        )
+    }
 }
@@ -1,8 +1,11 @@
-use wapc_guest::host_call;
+use crate::errors::{ErrorKind, Result};
+use crate::transport;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use wascc_codec::extras::*;
-use wascc_codec::{deserialize, serialize};
 
-const CAPID_EXTRAS: &str = "wascc:extras";
+/// The capability ID for the extras provider
+pub const CAPID_EXTRAS: &str = "wascc:extras";
 
 /// A hsot binding for the wascc:extras capability
 pub struct ExtrasHostBinding {
@@ -27,7 +30,7 @@ pub fn default() -> ExtrasHostBinding {
 
 impl ExtrasHostBinding {
     /// Queries the host for a random number within a specified range
-    pub fn get_random(&self, min: u32, max: u32) -> crate::HandlerResult<u32> {
+    pub fn get_random(&self, min: u32, max: u32) -> Result<u32> {
         let cmd = GeneratorRequest {
             min,
             max,
@@ -36,19 +39,12 @@ impl ExtrasHostBinding {
             guid: false,
         };
 
-        host_call(
-            &self.binding,
-            CAPID_EXTRAS,
-            OP_REQUEST_RANDOM,
-            &serialize(cmd)?,
-        )
-        .map(|v| deserialize::<GeneratorResult>(v.as_ref()).unwrap())
-        .map(|r| r.random_number)
-        .map_err(|e| e.into())
+        self.call(OP_REQUEST_RANDOM, cmd)
+            .map(|r: GeneratorResult| r.random_number)
     }
 
     /// Requests a newly generated GUID string from the host
-    pub fn get_guid(&self) -> crate::HandlerResult<String> {
+    pub fn get_guid(&self) -> Result<String> {
         let cmd = GeneratorRequest {
             guid: true,
             random: false,
@@ -56,20 +52,13 @@ impl ExtrasHostBinding {
             min: 0,
             max: 0,
         };
-        host_call(
-            &self.binding,
-            CAPID_EXTRAS,
-            OP_REQUEST_GUID,
-            &serialize(cmd)?,
-        )
-        .map(|v| deserialize::<GeneratorResult>(v.as_ref()).unwrap())
-        .map(|r| r.guid.unwrap_or("none".to_string()))
-        .map_err(|e| e.into())
+        self.call(OP_REQUEST_GUID, cmd)
+            .map(|r: GeneratorResult| r.guid.unwrap_or("none".to_string()))
     }
 
     /// Requests a sequence number from the host. Note that the sequence number will only be
     /// unique within the host, and is not globally unique
-    pub fn get_sequence_number(&self) -> crate::HandlerResult<u64> {
+    pub fn get_sequence_number(&self) -> Result<u64> {
         let cmd = GeneratorRequest {
             sequence: true,
             guid: false,
@@ -77,14 +66,17 @@ impl ExtrasHostBinding {
             min: 0,
             max: 0,
         };
-        host_call(
+        self.call(OP_REQUEST_SEQUENCE, cmd)
+            .map(|r: GeneratorResult| r.sequence_number)
+    }
+
+    fn call<T: DeserializeOwned>(&self, operation: &str, cmd: impl Serialize) -> Result<T> {
+        transport::call(
+            ErrorKind::ExtrasError,
             &self.binding,
             CAPID_EXTRAS,
-            OP_REQUEST_SEQUENCE,
-            &serialize(cmd)?,
+            operation,
+            cmd,
         )
-        .map(|v| deserialize::<GeneratorResult>(v.as_ref()).unwrap())
-        .map(|r| r.sequence_number)
-        .map_err(|e| e.into())
     }
 }
@@ -3,10 +3,12 @@
 //! This module contains the HTTP client through which actors consume
 //! the currently bound `wascap:http_client` capability provider
 
-use wapc_guest::host_call;
-use wascc_codec::{deserialize, http::*, serialize};
+use crate::transport;
+use wascc_codec::http::*;
 
-use crate::HandlerResult;
+use crate::errors::{ErrorKind, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 const CAPID_HTTPCLIENT: &str = "wascc:http_client";
 
@@ -36,14 +38,17 @@ pub fn default() -> HttpClientHostBinding {
 }
 
 impl HttpClientHostBinding {
-    pub fn request(&self, request: Request) -> HandlerResult<Response> {
-        host_call(
+    pub fn request(&self, request: Request) -> Result<Response> {
+        self.call(OP_PERFORM_REQUEST, request)
+    }
+
+    fn call<T: DeserializeOwned>(&self, operation: &str, cmd: impl Serialize) -> Result<T> {
+        transport::call(
+            ErrorKind::HttpClientError,
             &self.binding,
             CAPID_HTTPCLIENT,
-            OP_PERFORM_REQUEST,
-            &serialize(request)?,
+            operation,
+            cmd,
         )
-        .map(|r| deserialize::<Response>(r.as_ref()).unwrap())
-        .map_err(|e| e.into())
     }
 }
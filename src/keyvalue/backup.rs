@@ -0,0 +1,302 @@
+//! # Export and Import
+//!
+//! The key-value capability can't enumerate keys, so snapshots are taken from a
+//! `KeyRegistry`: a set of keys maintained alongside the writes made through it, together
+//! with each key's type and, for string values written with an expiry, when it expires.
+//! A registry can export its keys to a `Snapshot`, which serializes to JSON, and import a
+//! snapshot into any binding, which makes it useful for seeding test fixtures as well as
+//! for backups and for moving data between providers.
+//!
+//! Only strings can be given an expiry by the capability, so they are the only values
+//! that carry TTL metadata. A snapshot records the absolute expiry time (per the `clock`
+//! module), and entries that have expired by the time of an import are skipped.
+//!
+//! Membership of the registry's set can't expire along with the keys it lists, so an
+//! export also prunes keys whose value or metadata has gone, whether it expired or was
+//! deleted by other means. Until then `keys` may still list them. A key written by other
+//! means while an export is pruning it can be dropped from the registry; registering it
+//! again restores it.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::backup::{KeyRegistry, Snapshot};
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! clock.set(1000);
+//! clock::set_time_source(clock.clone());
+//! transport::set_transport(provider);
+//!
+//! let registry = KeyRegistry::new(keyvalue::default(), "keys");
+//! registry.set("session:1", "alice", Some(60))?;
+//! registry.list_add("log", "started")?;
+//! registry.set_add("admins", "alice")?;
+//!
+//! let json = registry.export()?.to_json()?;
+//!
+//! transport::set_transport(MockKeyValueProvider::with_clock(clock.clone()));
+//! clock.advance(10);
+//! let restored = KeyRegistry::new(keyvalue::default(), "keys");
+//! restored.import(&Snapshot::from_json(&json)?)?;
+//!
+//! let kv = keyvalue::default();
+//! assert_eq!(kv.get("session:1")?, Some("alice".to_string()));
+//! assert_eq!(kv.list_range("log", 0, -1)?, vec!["started"]);
+//! assert_eq!(restored.keys()?, vec!["admins", "log", "session:1"]);
+//! clock.advance(50);
+//! assert_eq!(kv.get("session:1")?, None);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::clock;
+use crate::errors::Result;
+use serde_derive::{Deserialize, Serialize};
+
+/// The type of value held by a registered key
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    String,
+    List,
+    Set,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyMetadata {
+    kind: KeyKind,
+    expires_at: Option<u64>,
+}
+
+/// The exported value of a single key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum SnapshotValue {
+    String(String),
+    List(Vec<String>),
+    Set(Vec<String>),
+}
+
+/// A single exported key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub key: String,
+    #[serde(flatten)]
+    pub value: SnapshotValue,
+    /// When the key expires, in seconds since the Unix epoch, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// The exported contents of a key registry
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The time the snapshot was taken, in seconds since the Unix epoch
+    pub taken_at: u64,
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl Snapshot {
+    /// Serializes the snapshot as a JSON document
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Parses a snapshot from a JSON document
+    pub fn from_json(json: &str) -> Result<Snapshot> {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Records the keys written through it so that they can be exported
+pub struct KeyRegistry {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+}
+
+impl KeyRegistry {
+    /// Creates a handle to the named registry. The registry's own bookkeeping is stored
+    /// under keys prefixed with its name.
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str) -> KeyRegistry {
+        KeyRegistry {
+            kv,
+            name: name.to_string(),
+        }
+    }
+
+    /// The binding through which the registry reads and writes
+    pub fn inner(&self) -> &KeyValueStoreHostBinding {
+        &self.kv
+    }
+
+    /// Records a key written by other means. For strings written with an expiry, pass the
+    /// same expiry so that it is included in exports.
+    pub fn register(&self, key: &str, kind: KeyKind, expires: Option<u32>) -> Result<()> {
+        let expires = expires.filter(|e| *e > 0);
+        let meta = KeyMetadata {
+            kind,
+            expires_at: match expires {
+                Some(secs) => Some(clock::now()? + secs as u64),
+                None => None,
+            },
+        };
+        self.kv.set_json(&self.meta_key(key), &meta, expires)?;
+        self.kv.set_add(&self.name, key)?;
+        Ok(())
+    }
+
+    /// Removes a key from the registry without deleting its value
+    pub fn unregister(&self, key: &str) -> Result<()> {
+        self.kv.set_remove(&self.name, key)?;
+        self.kv.del_key(&self.meta_key(key))
+    }
+
+    /// Returns the registered keys, including any that have expired or been deleted by
+    /// other means since the last export
+    pub fn keys(&self) -> Result<Vec<String>> {
+        self.kv.set_members(&self.name)
+    }
+
+    /// Sets a string value and registers its key
+    pub fn set(&self, key: &str, value: &str, expires: Option<u32>) -> Result<()> {
+        self.kv.set(key, value, expires)?;
+        self.register(key, KeyKind::String, expires)
+    }
+
+    /// Adds an item to a list and registers its key
+    pub fn list_add(&self, key: &str, item: &str) -> Result<usize> {
+        let count = self.kv.list_add(key, item)?;
+        self.register(key, KeyKind::List, None)?;
+        Ok(count)
+    }
+
+    /// Adds a value to a set and registers its key
+    pub fn set_add(&self, key: &str, value: &str) -> Result<usize> {
+        let count = self.kv.set_add(key, value)?;
+        self.register(key, KeyKind::Set, None)?;
+        Ok(count)
+    }
+
+    /// Deletes a key and removes it from the registry
+    pub fn del_key(&self, key: &str) -> Result<()> {
+        self.kv.del_key(key)?;
+        self.unregister(key)
+    }
+
+    /// Exports the current value of every registered key. Keys that no longer exist, or
+    /// whose metadata has been lost, are omitted and removed from the registry.
+    pub fn export(&self) -> Result<Snapshot> {
+        let mut entries = vec![];
+        for key in self.keys()? {
+            let meta: KeyMetadata = match self.kv.get_json(&self.meta_key(&key))? {
+                Some(meta) => meta,
+                None => {
+                    self.unregister(&key)?;
+                    continue;
+                }
+            };
+            let value = match meta.kind {
+                KeyKind::String => match self.kv.get(&key)? {
+                    Some(v) => SnapshotValue::String(v),
+                    None => {
+                        self.unregister(&key)?;
+                        continue;
+                    }
+                },
+                KeyKind::List if self.kv.exists(&key)? => {
+                    SnapshotValue::List(self.kv.list_range(&key, 0, -1)?)
+                }
+                KeyKind::Set if self.kv.exists(&key)? => {
+                    SnapshotValue::Set(self.kv.set_members(&key)?)
+                }
+                _ => {
+                    self.unregister(&key)?;
+                    continue;
+                }
+            };
+            entries.push(SnapshotEntry {
+                key,
+                value,
+                expires_at: meta.expires_at,
+            });
+        }
+        Ok(Snapshot {
+            taken_at: clock::now()?,
+            entries,
+        })
+    }
+
+    /// Writes every entry of the snapshot through this registry, replacing any existing
+    /// value of each key. Strings are given their remaining time to live, and entries that
+    /// have already expired are skipped.
+    pub fn import(&self, snapshot: &Snapshot) -> Result<()> {
+        let now = clock::now()?;
+        for entry in &snapshot.entries {
+            let expires = match entry.expires_at {
+                Some(at) if at <= now => continue,
+                Some(at) => Some((at - now).min(u32::MAX as u64) as u32),
+                None => None,
+            };
+            self.kv.del_key(&entry.key)?;
+            match entry.value {
+                SnapshotValue::String(ref v) => self.set(&entry.key, v, expires)?,
+                SnapshotValue::List(ref items) => {
+                    for item in items {
+                        self.kv.list_add(&entry.key, item)?;
+                    }
+                    self.register(&entry.key, KeyKind::List, None)?;
+                }
+                SnapshotValue::Set(ref members) => {
+                    for member in members {
+                        self.kv.set_add(&entry.key, member)?;
+                    }
+                    self.register(&entry.key, KeyKind::Set, None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn meta_key(&self, key: &str) -> String {
+        format!("{}:meta:{}", self.name, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::transport;
+
+    #[test]
+    fn export_prunes_keys_that_are_gone() {
+        let provider = MockKeyValueProvider::new();
+        let clock = provider.clock();
+        clock::set_time_source(clock.clone());
+        transport::set_transport(provider.clone());
+        let kv = crate::keyvalue::default();
+        let registry = KeyRegistry::new(kv.clone(), "keys");
+        registry.set("session:1", "alice", Some(60)).unwrap();
+        registry.set("user:1", "alice", None).unwrap();
+        registry.list_add("log", "started").unwrap();
+        registry.set_add("admins", "alice").unwrap();
+        kv.del_key("log").unwrap();
+        kv.del_key("keys:meta:admins").unwrap();
+
+        clock.advance(60);
+        let exported: Vec<String> = registry
+            .export()
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|e| e.key)
+            .collect();
+        assert_eq!(exported, vec!["user:1"]);
+        assert_eq!(registry.keys().unwrap(), vec!["user:1"]);
+        assert!(!kv.exists("keys:meta:log").unwrap());
+        assert!(kv.exists("admins").unwrap());
+    }
+}
@@ -0,0 +1,241 @@
+//! # Read-Through Cache
+//!
+//! A `CachedStore` keeps recently read values in the actor's memory so that hot keys (such
+//! as configuration) don't cost a host round trip on every request. Entries expire after a
+//! TTL measured with the `clock` module, and the least recently used entry is evicted once
+//! the cache is full. Keys that don't exist are cached too (with a separate, usually
+//! shorter, TTL) so that repeated misses don't reach the provider either.
+//!
+//! Writes made through a `CachedStore` invalidate the affected key, but the cache cannot see
+//! writes made through other bindings or by other actor instances; those become visible
+//! when the entry expires or is invalidated explicitly. To share a cache between requests,
+//! keep the `CachedStore` in a `lazy_static` (it is `Sync`).
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::cache::CachedStore;
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! clock::set_time_source(clock.clone());
+//! transport::set_transport(provider);
+//!
+//! let kv = keyvalue::default();
+//! let cache = CachedStore::new(kv.clone()).with_ttl(60).with_capacity(100);
+//! cache.set("feature.dark_mode", "on", None)?;
+//! assert_eq!(cache.get("feature.dark_mode")?, Some("on".to_string()));
+//!
+//! // a write that bypasses the cache isn't seen until the entry expires
+//! kv.set("feature.dark_mode", "off", None)?;
+//! assert_eq!(cache.get("feature.dark_mode")?, Some("on".to_string()));
+//! clock.advance(61);
+//! assert_eq!(cache.get("feature.dark_mode")?, Some("off".to_string()));
+//!
+//! // misses are cached as well, until invalidated
+//! assert_eq!(cache.get("feature.beta")?, None);
+//! kv.set("feature.beta", "on", None)?;
+//! assert_eq!(cache.get("feature.beta")?, None);
+//! cache.invalidate("feature.beta");
+//! assert_eq!(cache.get("feature.beta")?, Some("on".to_string()));
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::{decode_bytes, encode_bytes, KeyValueStoreHostBinding};
+use crate::clock;
+use crate::errors::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+const DEFAULT_CAPACITY: usize = 256;
+const DEFAULT_TTL: u32 = 30;
+const DEFAULT_NEGATIVE_TTL: u32 = 5;
+
+struct Entry {
+    value: Option<String>,
+    expires_at: u64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    // the key of every entry, ordered by when it was last used
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+/// A key-value binding that caches the results of `get` in the actor's memory
+pub struct CachedStore {
+    kv: KeyValueStoreHostBinding,
+    capacity: usize,
+    ttl: u32,
+    negative_ttl: u32,
+    cache: Mutex<Entries>,
+}
+
+impl CachedStore {
+    /// Creates a cache in front of the given binding
+    pub fn new(kv: KeyValueStoreHostBinding) -> CachedStore {
+        CachedStore {
+            kv,
+            capacity: DEFAULT_CAPACITY,
+            ttl: DEFAULT_TTL,
+            negative_ttl: DEFAULT_NEGATIVE_TTL,
+            cache: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// Sets the maximum number of keys held in the cache
+    pub fn with_capacity(mut self, capacity: usize) -> CachedStore {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets how long, in seconds, a value read from the store is cached
+    pub fn with_ttl(mut self, ttl: u32) -> CachedStore {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how long, in seconds, the absence of a key is cached. Zero disables negative
+    /// caching.
+    pub fn with_negative_ttl(mut self, ttl: u32) -> CachedStore {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    /// The binding behind the cache. Writes made directly through it are not seen by the
+    /// cache until the affected entries expire or are invalidated.
+    pub fn inner(&self) -> &KeyValueStoreHostBinding {
+        &self.kv
+    }
+
+    /// Obtains a single value, from the cache if possible
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let now = clock::now()?;
+        if let Some(value) = self.cache.lock().unwrap().lookup(key, now) {
+            return Ok(value);
+        }
+        let value = self.kv.get(key)?;
+        let ttl = if value.is_some() {
+            self.ttl
+        } else {
+            self.negative_ttl
+        };
+        if ttl > 0 && self.capacity > 0 {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(key, value.clone(), now + ttl as u64);
+            cache.evict_to(self.capacity);
+        }
+        Ok(value)
+    }
+
+    /// Obtains a value, from the cache if possible, and deserializes it from JSON
+    pub fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Obtains a binary value, from the cache if possible
+    pub fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.get(key)? {
+            Some(v) => Ok(Some(decode_bytes(v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets a value in the store, invalidating any cached value for the key
+    pub fn set(&self, key: &str, value: &str, expires: Option<u32>) -> Result<()> {
+        self.invalidate(key);
+        self.kv.set(key, value, expires)
+    }
+
+    /// Serializes a value as JSON and sets it in the store, invalidating any cached value
+    pub fn set_json<T: Serialize>(&self, key: &str, value: &T, expires: Option<u32>) -> Result<()> {
+        self.set(key, &serde_json::to_string(value)?, expires)
+    }
+
+    /// Sets a binary value in the store, invalidating any cached value
+    pub fn set_bytes(&self, key: &str, value: &[u8], expires: Option<u32>) -> Result<()> {
+        self.set(key, &encode_bytes(value), expires)
+    }
+
+    /// Performs an atomic increment operation, invalidating any cached value
+    pub fn atomic_add(&self, key: &str, value: i32) -> Result<i32> {
+        self.invalidate(key);
+        self.kv.atomic_add(key, value)
+    }
+
+    /// Removes a key from the store and the cache
+    pub fn del_key(&self, key: &str) -> Result<()> {
+        self.invalidate(key);
+        self.kv.del_key(key)
+    }
+
+    /// Discards any cached value for the key
+    pub fn invalidate(&self, key: &str) {
+        self.cache.lock().unwrap().remove(key);
+    }
+
+    /// Discards every cached value
+    pub fn clear(&self) {
+        *self.cache.lock().unwrap() = Entries::default();
+    }
+}
+
+impl Entries {
+    /// Returns the cached value, `Some(None)` being a cached miss, if there is a live entry
+    fn lookup(&mut self, key: &str, now: u64) -> Option<Option<String>> {
+        let expired = self.entries.get(key)?.expires_at <= now;
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(tick, key.to_string());
+        entry.last_used = tick;
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: &str, value: Option<String>, expires_at: u64) {
+        self.remove(key);
+        self.tick += 1;
+        self.recency.insert(self.tick, key.to_string());
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                value,
+                expires_at,
+                last_used: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    fn evict_to(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let oldest = match self.recency.keys().next() {
+                Some(tick) => *tick,
+                None => return,
+            };
+            if let Some(key) = self.recency.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
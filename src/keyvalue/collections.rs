@@ -0,0 +1,314 @@
+//! # Typed Collections
+//!
+//! Handles that present a key-value list, set or hash map with an interface resembling
+//! the standard collections, encoding elements as JSON. A `KvList<T>` and a `KvSet<T>` each
+//! wrap a single list or set key. The capability has no hash type, so a `KvMap<T>` stores
+//! each field's value under its own key (`{key}:field:{name}`) and keeps the field names
+//! in a set (`{key}:fields`) so that the map can be enumerated.
+//!
+//! Elements are compared by their JSON encoding, so values must serialize identically each
+//! time (e.g. avoid `HashMap` fields, whose order is unspecified). The capability also has
+//! no length or membership queries, so `len` and `contains` fetch the whole collection.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::collections::{KvList, KvMap, KvSet};
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let kv = keyvalue::default();
+//!
+//! let scores = KvList::<u32>::new(kv.clone(), "scores");
+//! scores.push(&10)?;
+//! scores.push(&30)?;
+//! assert_eq!(scores.iter().collect::<Result<Vec<_>, _>>()?, vec![10, 30]);
+//!
+//! let tags = KvSet::<String>::new(kv.clone(), "tags");
+//! tags.insert(&"rust".to_string())?;
+//! assert!(tags.contains(&"rust".to_string())?);
+//! let other = KvSet::<String>::new(kv.namespace("blog"), "tags");
+//! other.insert(&"wasm".to_string())?;
+//! assert_eq!(tags.union(&other)?, vec!["rust", "wasm"]);
+//!
+//! let users = KvMap::<(String, u8)>::new(kv, "users");
+//! users.insert("u1", &("alice".to_string(), 30))?;
+//! users.insert("u2", &("bob".to_string(), 25))?;
+//! assert_eq!(users.get("u1")?, Some(("alice".to_string(), 30)));
+//! assert_eq!(users.remove("u2")?, Some(("bob".to_string(), 25)));
+//! assert_eq!(users.keys()?, vec!["u1"]);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::{from_json_values, KeyValueStoreHostBinding};
+use crate::errors::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+
+const PAGE_SIZE: usize = 100;
+
+/// A list of JSON-encoded elements stored at a single key
+pub struct KvList<T> {
+    kv: KeyValueStoreHostBinding,
+    key: String,
+    _element: PhantomData<fn() -> T>,
+}
+
+impl<T> KvList<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a handle to the list at the given key
+    pub fn new(kv: KeyValueStoreHostBinding, key: &str) -> KvList<T> {
+        KvList {
+            kv,
+            key: key.to_string(),
+            _element: PhantomData,
+        }
+    }
+
+    /// Appends an element to the list, returning the new length
+    pub fn push(&self, item: &T) -> Result<usize> {
+        self.kv.list_add_json(&self.key, item)
+    }
+
+    /// Removes every occurrence of the element, returning the new length
+    pub fn remove(&self, item: &T) -> Result<usize> {
+        self.kv.list_del_item_json(&self.key, item)
+    }
+
+    /// Returns the elements between the given indices (inclusive), which may be negative
+    /// to count from the end of the list
+    pub fn range(&self, start: isize, stop_inclusive: isize) -> Result<Vec<T>> {
+        self.kv.list_range_json(&self.key, start, stop_inclusive)
+    }
+
+    /// Indicates whether the list contains the element
+    pub fn contains(&self, item: &T) -> Result<bool> {
+        let encoded = serde_json::to_string(item)?;
+        for value in self.kv.list_iter(&self.key, PAGE_SIZE) {
+            if value? == encoded {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the number of elements in the list
+    pub fn len(&self) -> Result<usize> {
+        let mut len = 0;
+        for page in self.kv.list_pages(&self.key, PAGE_SIZE) {
+            len += page?.len();
+        }
+        Ok(len)
+    }
+
+    /// Indicates whether the list has no elements
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.kv.list_range(&self.key, 0, 0)?.is_empty())
+    }
+
+    /// Returns an iterator over the elements, fetched from the store a page at a time
+    pub fn iter(&self) -> impl Iterator<Item = Result<T>> + '_ {
+        self.kv
+            .list_iter(&self.key, PAGE_SIZE)
+            .map(|v| Ok(serde_json::from_str(&v?)?))
+    }
+
+    /// Removes all elements from the list
+    pub fn clear(&self) -> Result<()> {
+        self.kv.del_key(&self.key)
+    }
+}
+
+/// A set of JSON-encoded elements stored at a single key
+pub struct KvSet<T> {
+    kv: KeyValueStoreHostBinding,
+    key: String,
+    _element: PhantomData<fn() -> T>,
+}
+
+impl<T> KvSet<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a handle to the set at the given key
+    pub fn new(kv: KeyValueStoreHostBinding, key: &str) -> KvSet<T> {
+        KvSet {
+            kv,
+            key: key.to_string(),
+            _element: PhantomData,
+        }
+    }
+
+    /// Adds an element to the set, returning the new number of elements
+    pub fn insert(&self, item: &T) -> Result<usize> {
+        self.kv.set_add_json(&self.key, item)
+    }
+
+    /// Removes an element from the set, returning the new number of elements
+    pub fn remove(&self, item: &T) -> Result<usize> {
+        self.kv.set_remove_json(&self.key, item)
+    }
+
+    /// Indicates whether the set contains the element
+    pub fn contains(&self, item: &T) -> Result<bool> {
+        let encoded = serde_json::to_string(item)?;
+        Ok(self.kv.set_members(&self.key)?.contains(&encoded))
+    }
+
+    /// Returns the number of elements in the set
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.kv.set_members(&self.key)?.len())
+    }
+
+    /// Indicates whether the set has no elements
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns an iterator over the elements of the set
+    pub fn iter(&self) -> impl Iterator<Item = Result<T>> + '_ {
+        self.kv
+            .set_iter(&self.key)
+            .map(|v| Ok(serde_json::from_str(&v?)?))
+    }
+
+    /// Returns the elements that are in this set or the other
+    pub fn union(&self, other: &KvSet<T>) -> Result<Vec<T>> {
+        let members = match self.keys_with(other) {
+            Some(keys) => self.kv.set_union(keys)?,
+            None => {
+                let mut members: BTreeSet<_> =
+                    self.kv.set_members(&self.key)?.into_iter().collect();
+                members.extend(other.kv.set_members(&other.key)?);
+                members.into_iter().collect()
+            }
+        };
+        from_json_values(members)
+    }
+
+    /// Returns the elements that are in both this set and the other
+    pub fn intersection(&self, other: &KvSet<T>) -> Result<Vec<T>> {
+        let members = match self.keys_with(other) {
+            Some(keys) => self.kv.set_intersect(keys)?,
+            None => {
+                let theirs: BTreeSet<_> = other.kv.set_members(&other.key)?.into_iter().collect();
+                let mut members = self.kv.set_members(&self.key)?;
+                members.retain(|m| theirs.contains(m));
+                members
+            }
+        };
+        from_json_values(members)
+    }
+
+    /// Removes all elements from the set
+    pub fn clear(&self) -> Result<()> {
+        self.kv.del_key(&self.key)
+    }
+
+    /// Returns the keys of both sets relative to this set's namespace, if the provider can
+    /// combine them in a single operation
+    fn keys_with(&self, other: &KvSet<T>) -> Option<Vec<String>> {
+        if self.kv.binding != other.kv.binding {
+            return None;
+        }
+        let qualified = other.kv.qualify(&other.key);
+        let other_key = self.kv.unqualify(&qualified)?;
+        Some(vec![self.key.clone(), other_key.to_string()])
+    }
+}
+
+/// A map from field names to JSON-encoded values, stored under keys sharing a prefix
+pub struct KvMap<T> {
+    kv: KeyValueStoreHostBinding,
+    key: String,
+    _value: PhantomData<fn() -> T>,
+}
+
+impl<T> KvMap<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a handle to the map stored under the given key
+    pub fn new(kv: KeyValueStoreHostBinding, key: &str) -> KvMap<T> {
+        KvMap {
+            kv,
+            key: key.to_string(),
+            _value: PhantomData,
+        }
+    }
+
+    /// Sets the value of a field
+    pub fn insert(&self, field: &str, value: &T) -> Result<()> {
+        self.kv.set_json(&self.field_key(field), value, None)?;
+        self.kv.set_add(&self.fields_key(), field)?;
+        Ok(())
+    }
+
+    /// Obtains the value of a field
+    pub fn get(&self, field: &str) -> Result<Option<T>> {
+        self.kv.get_json(&self.field_key(field))
+    }
+
+    /// Removes a field, returning its previous value
+    pub fn remove(&self, field: &str) -> Result<Option<T>> {
+        let old = self.get(field)?;
+        self.kv.set_remove(&self.fields_key(), field)?;
+        self.kv.del_key(&self.field_key(field))?;
+        Ok(old)
+    }
+
+    /// Indicates whether the map has a value for the field
+    pub fn contains_key(&self, field: &str) -> Result<bool> {
+        self.kv.exists(&self.field_key(field))
+    }
+
+    /// Returns the names of the fields in the map
+    pub fn keys(&self) -> Result<Vec<String>> {
+        self.kv.set_members(&self.fields_key())
+    }
+
+    /// Returns the number of fields in the map
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.keys()?.len())
+    }
+
+    /// Indicates whether the map has no fields
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Returns an iterator over the fields and their values, fetching each value from the
+    /// store as it is reached. Fields removed since the iterator was created are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, T)>> + '_ {
+        self.kv
+            .set_iter(&self.fields_key())
+            .filter_map(move |field| match field {
+                Ok(field) => match self.get(&field) {
+                    Ok(Some(value)) => Some(Ok((field, value))),
+                    Ok(None) => None,
+                    Err(e) => Some(Err(e)),
+                },
+                Err(e) => Some(Err(e)),
+            })
+    }
+
+    /// Removes every field from the map
+    pub fn clear(&self) -> Result<()> {
+        for field in self.keys()? {
+            self.kv.del_key(&self.field_key(&field))?;
+        }
+        self.kv.del_key(&self.fields_key())
+    }
+
+    fn field_key(&self, field: &str) -> String {
+        format!("{}:field:{}", self.key, field)
+    }
+
+    fn fields_key(&self) -> String {
+        format!("{}:fields", self.key)
+    }
+}
@@ -0,0 +1,243 @@
+//! # Secondary Indexes
+//!
+//! A `Repository<T>` stores JSON entities by ID and maintains secondary indexes over them
+//! in key-value sets, one set per indexed value holding the IDs of the entities with that
+//! value. Each index is defined by a function extracting the values to index from an
+//! entity, so an entity may appear under any number of values (e.g. one per tag).
+//!
+//! Writes add the new index entries before storing the entity and remove stale entries
+//! afterwards, so an actor that fails part-way through leaves extra index entries rather
+//! than missing ones. Queries re-check every entity they load against the index
+//! definition and skip any that no longer match, so extra entries are never returned.
+//!
+//! Keys are laid out under the repository name: `{name}:doc:{id}` for entities,
+//! `{name}:ids` for the set of all IDs and `{name}:idx:{index}:{value}` for index sets.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::index::Repository;
+//! use serde_derive::{Deserialize, Serialize};
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+//! struct Order {
+//!     customer: String,
+//!     status: String,
+//! }
+//!
+//! let orders = Repository::new(keyvalue::default(), "orders")
+//!     .with_index("customer", |o: &Order| vec![o.customer.clone()])
+//!     .with_index("status", |o: &Order| vec![o.status.clone()]);
+//!
+//! let order = |customer: &str, status: &str| Order {
+//!     customer: customer.to_string(),
+//!     status: status.to_string(),
+//! };
+//! orders.put("1", &order("alice", "open"))?;
+//! orders.put("2", &order("alice", "shipped"))?;
+//! orders.put("3", &order("bob", "open"))?;
+//!
+//! assert_eq!(orders.find_ids("status", "open")?, vec!["1", "3"]);
+//! assert_eq!(orders.find_ids_all(&[("customer", "alice"), ("status", "open")])?, vec!["1"]);
+//!
+//! orders.put("1", &order("alice", "shipped"))?;
+//! orders.delete("3")?;
+//! assert!(orders.find("status", "open")?.is_empty());
+//! assert_eq!(orders.find_ids("status", "shipped")?, vec!["1", "2"]);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::errors::{self, ErrorKind, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+type Extractor<T> = Box<dyn Fn(&T) -> Vec<String> + Send + Sync>;
+
+struct Index<T> {
+    name: String,
+    extract: Extractor<T>,
+}
+
+/// Stores entities of type `T` by ID, maintaining the registered secondary indexes
+pub struct Repository<T> {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+    indexes: Vec<Index<T>>,
+}
+
+impl<T> Repository<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a repository storing its entities and indexes under the given name
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str) -> Repository<T> {
+        Repository {
+            kv,
+            name: name.to_string(),
+            indexes: vec![],
+        }
+    }
+
+    /// Registers an index whose values for an entity are produced by `extract`. Entities
+    /// stored before the index was registered are only indexed after `reindex`.
+    pub fn with_index<F>(mut self, name: &str, extract: F) -> Repository<T>
+    where
+        F: Fn(&T) -> Vec<String> + Send + Sync + 'static,
+    {
+        self.indexes.push(Index {
+            name: name.to_string(),
+            extract: Box::new(extract),
+        });
+        self
+    }
+
+    /// Obtains the entity with the given ID
+    pub fn get(&self, id: &str) -> Result<Option<T>> {
+        self.kv.get_json(&self.doc_key(id))
+    }
+
+    /// Stores an entity under the given ID, updating the indexes to match it
+    pub fn put(&self, id: &str, entity: &T) -> Result<()> {
+        let old = self.get(id)?;
+        for index in &self.indexes {
+            let new_values = values(index, Some(entity));
+            for value in new_values.difference(&values(index, old.as_ref())) {
+                self.kv.set_add(&self.index_key(&index.name, value), id)?;
+            }
+        }
+        self.kv.set_json(&self.doc_key(id), entity, None)?;
+        self.kv.set_add(&self.ids_key(), id)?;
+        for index in &self.indexes {
+            let new_values = values(index, Some(entity));
+            for value in values(index, old.as_ref()).difference(&new_values) {
+                self.kv
+                    .set_remove(&self.index_key(&index.name, value), id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the entity with the given ID and its index entries, returning the entity
+    pub fn delete(&self, id: &str) -> Result<Option<T>> {
+        let old = self.get(id)?;
+        self.kv.del_key(&self.doc_key(id))?;
+        self.kv.set_remove(&self.ids_key(), id)?;
+        if let Some(ref entity) = old {
+            for index in &self.indexes {
+                for value in values(index, Some(entity)) {
+                    self.kv
+                        .set_remove(&self.index_key(&index.name, &value), id)?;
+                }
+            }
+        }
+        Ok(old)
+    }
+
+    /// Returns the IDs of every stored entity
+    pub fn ids(&self) -> Result<Vec<String>> {
+        self.kv.set_members(&self.ids_key())
+    }
+
+    /// Returns the IDs of the entities having the given value in the named index
+    pub fn find_ids(&self, index: &str, value: &str) -> Result<Vec<String>> {
+        self.find_ids_all(&[(index, value)])
+    }
+
+    /// Returns the entities having the given value in the named index
+    pub fn find(&self, index: &str, value: &str) -> Result<Vec<T>> {
+        self.find_all(&[(index, value)])
+    }
+
+    /// Returns the IDs of the entities matching every `(index, value)` criterion
+    pub fn find_ids_all(&self, criteria: &[(&str, &str)]) -> Result<Vec<String>> {
+        Ok(self
+            .query(criteria)?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    /// Returns the entities matching every `(index, value)` criterion
+    pub fn find_all(&self, criteria: &[(&str, &str)]) -> Result<Vec<T>> {
+        Ok(self
+            .query(criteria)?
+            .into_iter()
+            .map(|(_, entity)| entity)
+            .collect())
+    }
+
+    /// Rebuilds every index from the stored entities, e.g. after registering a new index
+    /// or changing how an index extracts its values. Stale entries for values that no
+    /// entity has any more are not removed.
+    pub fn reindex(&self) -> Result<()> {
+        for id in self.ids()? {
+            let entity = match self.get(&id)? {
+                Some(entity) => entity,
+                None => continue,
+            };
+            for index in &self.indexes {
+                for value in values(index, Some(&entity)) {
+                    self.kv.set_add(&self.index_key(&index.name, &value), &id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn query(&self, criteria: &[(&str, &str)]) -> Result<Vec<(String, T)>> {
+        let mut checks = vec![];
+        let mut keys = vec![];
+        for (name, value) in criteria {
+            let index = self
+                .indexes
+                .iter()
+                .find(|i| i.name == *name)
+                .ok_or_else(|| {
+                    errors::new(ErrorKind::MiscError(
+                        format!("No index named '{}' in repository '{}'", name, self.name).into(),
+                    ))
+                })?;
+            checks.push((index, *value));
+            keys.push(self.index_key(name, value));
+        }
+        let ids = match keys.len() {
+            0 => self.ids()?,
+            1 => self.kv.set_members(&keys[0])?,
+            _ => self.kv.set_intersect(keys)?,
+        };
+        let mut found = vec![];
+        for id in ids {
+            if let Some(entity) = self.get(&id)? {
+                if checks
+                    .iter()
+                    .all(|(index, value)| (index.extract)(&entity).iter().any(|v| v == value))
+                {
+                    found.push((id, entity));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn doc_key(&self, id: &str) -> String {
+        format!("{}:doc:{}", self.name, id)
+    }
+
+    fn ids_key(&self) -> String {
+        format!("{}:ids", self.name)
+    }
+
+    fn index_key(&self, index: &str, value: &str) -> String {
+        format!("{}:idx:{}:{}", self.name, index, value)
+    }
+}
+
+fn values<T>(index: &Index<T>, entity: Option<&T>) -> BTreeSet<String> {
+    entity
+        .map(|e| (index.extract)(e).into_iter().collect())
+        .unwrap_or_default()
+}
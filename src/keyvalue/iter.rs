@@ -0,0 +1,154 @@
+//! # Paginated Iteration
+//!
+//! Iterators that page through large key-value collections with one host call per page,
+//! so that an actor never holds more than a page of a list in memory. Pages are addressed
+//! by index, so items added to or removed from the front of a list while it is being
+//! iterated may cause items to be skipped or repeated.
+//!
+//! The key-value capability has no cursor for sets, so `set_iter` retrieves the members in
+//! a single call when the first member is requested.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let kv = keyvalue::default();
+//! for n in 0..10 {
+//!     kv.list_add("numbers", &n.to_string())?;
+//! }
+//!
+//! let pages: Vec<Vec<String>> = kv.list_pages("numbers", 4).collect::<Result<_, _>>()?;
+//! assert_eq!(pages.iter().map(|p| p.len()).collect::<Vec<_>>(), vec![4, 4, 2]);
+//!
+//! let total: u32 = kv
+//!     .list_iter("numbers", 3)
+//!     .map(|n| n.map(|n| n.parse::<u32>().unwrap()))
+//!     .sum::<Result<u32, _>>()?;
+//! assert_eq!(total, 45);
+//!
+//! kv.set_add("tags", "rust")?;
+//! kv.set_add("tags", "wasm")?;
+//! assert_eq!(kv.set_iter("tags").collect::<Result<Vec<_>, _>>()?, vec!["rust", "wasm"]);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::errors::Result;
+
+/// An iterator over the pages of a list, created by `KeyValueStoreHostBinding::list_pages`
+pub struct ListPages<'a> {
+    kv: &'a KeyValueStoreHostBinding,
+    key: String,
+    page_size: usize,
+    next: usize,
+    done: bool,
+}
+
+impl<'a> ListPages<'a> {
+    pub(crate) fn new(kv: &'a KeyValueStoreHostBinding, key: &str, page_size: usize) -> Self {
+        ListPages {
+            kv,
+            key: key.to_string(),
+            page_size: page_size.max(1),
+            next: 0,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for ListPages<'_> {
+    type Item = Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let stop = self.next + self.page_size - 1;
+        match self
+            .kv
+            .list_range(&self.key, self.next as isize, stop as isize)
+        {
+            Ok(page) => {
+                self.done = page.len() < self.page_size;
+                self.next += page.len();
+                if page.is_empty() {
+                    None
+                } else {
+                    Some(Ok(page))
+                }
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// An iterator over the items of a list, fetched a page at a time, created by
+/// `KeyValueStoreHostBinding::list_iter`
+pub struct ListIter<'a> {
+    pages: ListPages<'a>,
+    page: std::vec::IntoIter<String>,
+}
+
+impl<'a> ListIter<'a> {
+    pub(crate) fn new(kv: &'a KeyValueStoreHostBinding, key: &str, page_size: usize) -> Self {
+        ListIter {
+            pages: ListPages::new(kv, key, page_size),
+            page: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl Iterator for ListIter<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.page.next() {
+                return Some(Ok(item));
+            }
+            match self.pages.next()? {
+                Ok(page) => self.page = page.into_iter(),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// An iterator over the members of a set, created by `KeyValueStoreHostBinding::set_iter`
+pub struct SetIter<'a> {
+    kv: &'a KeyValueStoreHostBinding,
+    key: String,
+    members: Option<std::vec::IntoIter<String>>,
+}
+
+impl<'a> SetIter<'a> {
+    pub(crate) fn new(kv: &'a KeyValueStoreHostBinding, key: &str) -> Self {
+        SetIter {
+            kv,
+            key: key.to_string(),
+            members: None,
+        }
+    }
+}
+
+impl Iterator for SetIter<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.members.is_none() {
+            match self.kv.set_members(&self.key) {
+                Ok(members) => self.members = Some(members.into_iter()),
+                Err(e) => {
+                    self.members = Some(Vec::new().into_iter());
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.members.as_mut().and_then(|m| m.next()).map(Ok)
+    }
+}
@@ -0,0 +1,304 @@
+//! # Leases
+//!
+//! A lease grants one actor instance exclusive ownership of a named job or resource for a
+//! limited time. Acquisition is decided by `atomic_add` on a claim counter: only the caller
+//! that moves the counter from zero to one obtains the lease, after which the claim and the
+//! owner record are given the lease's TTL so that a crashed owner cannot hold the lease
+//! forever. The owner record holds a token (a GUID from the `wascc:extras` provider by
+//! default) which is checked before the lease is renewed or released.
+//!
+//! The capability can't make that check and the write that follows it atomic, so a lease
+//! that expired in between could be taken over, and then overwritten or deleted by the old
+//! owner. To guard against this, each owner also tracks its lease's term with the `clock`
+//! module, and refuses to renew or release a lease once less than a safety margin (a tenth
+//! of the TTL, and at least one second) remains of it, both before and after the check. The
+//! race therefore remains only if the final check and write are separated by more than the
+//! margin, or the clock used by the actor runs slow compared with the provider's. A lease
+//! that is left to run out near the end of its term simply expires.
+//!
+//! Dropping a `Lease` releases it; use `Lease::release` to observe the outcome.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::lease::Lease;
+//! use actor::testing::{extras::MockExtrasProvider, keyvalue::MockKeyValueProvider, MockHost};
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! clock::set_time_source(clock.clone());
+//! transport::set_transport(
+//!     MockHost::new()
+//!         .with_provider(keyvalue::CAPID_KEYVALUE, provider)
+//!         .with_provider(extras::CAPID_EXTRAS, MockExtrasProvider::new()),
+//! );
+//!
+//! let kv = keyvalue::default();
+//! let lease = Lease::acquire(&kv, "nightly-report", 30)?.expect("lease is free");
+//! assert!(Lease::acquire(&kv, "nightly-report", 30)?.is_none());
+//!
+//! clock.advance(20);
+//! assert!(lease.renew()?);
+//! clock.advance(20);
+//! assert!(lease.is_held()?);
+//!
+//! // once the lease lapses, someone else may take it and the old guard can't release it
+//! clock.advance(31);
+//! let next = Lease::acquire_as(&kv, "nightly-report", 30, "other-instance")?.unwrap();
+//! assert!(!lease.release()?);
+//! assert_eq!(keyvalue::lease::holder(&kv, "nightly-report")?, Some("other-instance".to_string()));
+//! drop(next);
+//! assert_eq!(keyvalue::lease::holder(&kv, "nightly-report")?, None);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::clock;
+use crate::errors::{self, ErrorKind, Result};
+use crate::extras;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Exclusive ownership of a named lease, released when dropped
+pub struct Lease {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+    owner: String,
+    ttl: u32,
+    /// When the lease was last acquired or renewed, per the `clock` module
+    renewed_at: AtomicU64,
+    released: bool,
+}
+
+/// Returns the owner token of the current holder of the named lease, if any
+pub fn holder(kv: &KeyValueStoreHostBinding, name: &str) -> Result<Option<String>> {
+    kv.get(name)
+}
+
+impl Lease {
+    /// Attempts to acquire the named lease for `ttl` seconds, identifying the owner with a
+    /// GUID obtained from the default `wascc:extras` binding. Returns `None` if the lease is
+    /// held by someone else.
+    pub fn acquire(kv: &KeyValueStoreHostBinding, name: &str, ttl: u32) -> Result<Option<Lease>> {
+        let owner = extras::default().get_guid()?;
+        Lease::acquire_as(kv, name, ttl, &owner)
+    }
+
+    /// Attempts to acquire the named lease for `ttl` seconds on behalf of the given owner
+    /// token. The TTL must be non-zero.
+    pub fn acquire_as(
+        kv: &KeyValueStoreHostBinding,
+        name: &str,
+        ttl: u32,
+        owner: &str,
+    ) -> Result<Option<Lease>> {
+        if ttl == 0 {
+            return Err(errors::new(ErrorKind::MiscError(
+                format!("Lease '{}' must be acquired with a non-zero TTL", name).into(),
+            )));
+        }
+        let now = clock::now()?;
+        let claim = claim_key(name);
+        if kv.exists(name)? || kv.atomic_add(&claim, 1)? != 1 {
+            return Ok(None);
+        }
+        // the claim must never outlive the owner record, or the lease could not be
+        // reacquired once the owner record expires; until it is given the lease's TTL it
+        // has no expiry at all, so give it up if either write fails
+        if let Err(e) = kv
+            .set(&claim, "1", Some(ttl))
+            .and_then(|_| kv.set(name, owner, Some(ttl)))
+        {
+            let _ = kv.del_key(&claim);
+            return Err(e);
+        }
+        Ok(Some(Lease {
+            kv: kv.clone(),
+            name: name.to_string(),
+            owner: owner.to_string(),
+            ttl,
+            renewed_at: AtomicU64::new(now),
+            released: false,
+        }))
+    }
+
+    /// The name of the lease
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The token identifying this owner of the lease
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Indicates whether this owner still holds the lease
+    pub fn is_held(&self) -> Result<bool> {
+        Ok(holder(&self.kv, &self.name)?.as_deref() == Some(self.owner.as_str()))
+    }
+
+    /// Extends the lease by its TTL, measured from now. Returns `false` if the lease has
+    /// already expired or been taken over, or is too close to expiring to be renewed
+    /// safely, in which case it is not renewed.
+    pub fn renew(&self) -> Result<bool> {
+        let now = clock::now()?;
+        if !self.in_term()? || !self.is_held()? || !self.in_term()? {
+            return Ok(false);
+        }
+        self.kv.set(&claim_key(&self.name), "1", Some(self.ttl))?;
+        self.kv.set(&self.name, &self.owner, Some(self.ttl))?;
+        self.renewed_at.store(now, Ordering::SeqCst);
+        Ok(true)
+    }
+
+    /// Releases the lease, returning `false` if it was no longer held by this owner or was
+    /// too close to expiring to be released safely (in which case nothing is deleted)
+    pub fn release(mut self) -> Result<bool> {
+        self.released = true;
+        self.release_held()
+    }
+
+    fn release_held(&self) -> Result<bool> {
+        if !self.in_term()? || !self.is_held()? || !self.in_term()? {
+            return Ok(false);
+        }
+        // delete the owner record first: a contender that slips in before the claim is
+        // deleted finds the claim taken and simply fails to acquire
+        self.kv.del_key(&self.name)?;
+        self.kv.del_key(&claim_key(&self.name))?;
+        Ok(true)
+    }
+
+    /// Indicates whether more than the safety margin remains of the lease's term, so that
+    /// it can't have expired by the time a write made now reaches the provider
+    fn in_term(&self) -> Result<bool> {
+        let margin = (self.ttl / 10).max(1) as u64;
+        let expires_at = self.renewed_at.load(Ordering::SeqCst) + self.ttl as u64;
+        Ok(clock::now()? + margin < expires_at)
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.release_held();
+        }
+    }
+}
+
+fn claim_key(name: &str) -> String {
+    format!("{}:claim", name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::testing::MockClock;
+    use crate::transport::{self, HostTransport};
+    use crate::HandlerResult;
+    use std::sync::{Arc, Mutex};
+    use wascc_codec::keyvalue::{GetRequest, OP_GET, OP_SET};
+
+    type Hook = Box<dyn FnOnce() + Send>;
+
+    /// Forwards calls to a mock provider, running a hook right after the next read of a
+    /// given key, i.e. between an owner's check and the write that follows it
+    #[derive(Clone)]
+    struct Interleave {
+        provider: MockKeyValueProvider,
+        hook: Arc<Mutex<Option<(String, Hook)>>>,
+    }
+
+    impl HostTransport for Interleave {
+        fn host_call(
+            &self,
+            binding: &str,
+            capid: &str,
+            operation: &str,
+            msg: &[u8],
+        ) -> HandlerResult<Vec<u8>> {
+            let resp = self.provider.host_call(binding, capid, operation, msg)?;
+            if operation == OP_GET {
+                let key = wascc_codec::deserialize::<GetRequest>(msg)?.key;
+                let mut hook = self.hook.lock().unwrap();
+                if hook.as_ref().is_some_and(|(k, _)| *k == key) {
+                    let (_, f) = hook.take().unwrap();
+                    drop(hook);
+                    f();
+                }
+            }
+            Ok(resp)
+        }
+    }
+
+    fn setup() -> (Interleave, MockClock, KeyValueStoreHostBinding) {
+        let provider = MockKeyValueProvider::new();
+        let clock = provider.clock();
+        clock::set_time_source(clock.clone());
+        let interleave = Interleave {
+            provider,
+            hook: Arc::new(Mutex::new(None)),
+        };
+        transport::set_transport(interleave.clone());
+        (interleave, clock, crate::keyvalue::default())
+    }
+
+    /// Arms a hook that lets the lease expire and another owner take it over
+    fn take_over_after_check(interleave: &Interleave, clock: &MockClock) {
+        let clock = clock.clone();
+        let hook: Hook = Box::new(move || {
+            clock.advance(31);
+            let kv = crate::keyvalue::default();
+            std::mem::forget(Lease::acquire_as(&kv, "job", 30, "b").unwrap().unwrap());
+        });
+        *interleave.hook.lock().unwrap() = Some(("job".to_string(), hook));
+    }
+
+    #[test]
+    fn renew_after_takeover_between_check_and_write() {
+        let (interleave, clock, kv) = setup();
+        let lease = Lease::acquire_as(&kv, "job", 30, "a").unwrap().unwrap();
+        clock.advance(10);
+        take_over_after_check(&interleave, &clock);
+        assert!(!lease.renew().unwrap());
+        assert_eq!(holder(&kv, "job").unwrap(), Some("b".to_string()));
+    }
+
+    #[test]
+    fn release_after_takeover_between_check_and_write() {
+        let (interleave, clock, kv) = setup();
+        let lease = Lease::acquire_as(&kv, "job", 30, "a").unwrap().unwrap();
+        clock.advance(10);
+        take_over_after_check(&interleave, &clock);
+        assert!(!lease.release().unwrap());
+        assert_eq!(holder(&kv, "job").unwrap(), Some("b".to_string()));
+    }
+
+    #[test]
+    fn lease_near_expiry_is_neither_renewed_nor_released() {
+        let (_, clock, kv) = setup();
+        let lease = Lease::acquire_as(&kv, "job", 30, "a").unwrap().unwrap();
+        clock.advance(27);
+        assert!(lease.is_held().unwrap());
+        assert!(!lease.renew().unwrap());
+        drop(lease);
+        assert_eq!(holder(&kv, "job").unwrap(), Some("a".to_string()));
+    }
+
+    #[test]
+    fn zero_ttl_is_rejected() {
+        let (_, _, kv) = setup();
+        assert!(Lease::acquire_as(&kv, "job", 0, "a").is_err());
+    }
+
+    #[test]
+    fn failed_acquisition_leaves_the_lease_free() {
+        let (interleave, _, kv) = setup();
+        interleave.provider.fail_next(OP_SET, "job:claim");
+        assert!(Lease::acquire_as(&kv, "job", 30, "a").is_err());
+        interleave.provider.fail_next(OP_SET, "job");
+        assert!(Lease::acquire_as(&kv, "job", 30, "a").is_err());
+        assert!(Lease::acquire_as(&kv, "job", 30, "b").unwrap().is_some());
+    }
+}
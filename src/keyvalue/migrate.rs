@@ -0,0 +1,285 @@
+//! # Schema Migrations
+//!
+//! Two complementary tools for evolving the shape of stored data. A `Migrator` runs an
+//! ordered list of migration steps against the store, recording the schema version it
+//! has reached so that each step runs once no matter how many actor instances start up
+//! concurrently: before running step `n`, an instance must move the claim counter for
+//! version `n` from zero to one with `atomic_add`. A step that fails releases its claim so
+//! that it can be retried, as does a step whose new version can't be recorded, which means
+//! that such a step runs again even though it succeeded. An instance that stops mid-step
+//! leaves the claim in place, and it must be released with `Migrator::release` once the
+//! step has been checked.
+//!
+//! An `Upcaster<T>` instead upgrades documents one at a time as they are read. Documents
+//! are stored with the schema version they were written at, and on read are passed through
+//! each upcasting step from that version to the latest before being deserialized.
+//! Documents written without a version (e.g. by `set_json`) are treated as version 0.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::migrate::{Migrator, Upcaster};
+//! use serde_derive::{Deserialize, Serialize};
+//! use serde_json::json;
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let kv = keyvalue::default();
+//! let migrator = Migrator::new(kv.clone(), "schema")
+//!     .with_step("seed plans", |kv| kv.set_add("plans", "free").map(|_| ()))
+//!     .with_step("add pro plan", |kv| kv.set_add("plans", "pro").map(|_| ()));
+//! assert_eq!(migrator.run()?, 2);
+//! assert_eq!(migrator.run()?, 2);
+//! assert_eq!(kv.set_members("plans")?, vec!["free", "pro"]);
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct User {
+//!     first: String,
+//!     last: String,
+//! }
+//!
+//! // version 0 stored the whole name in a single field
+//! kv.set_json("user:1", &json!({ "name": "Ada Lovelace" }), None)?;
+//! let users = Upcaster::<User>::new(kv.clone()).with_step(|mut doc| {
+//!     let name = doc["name"].as_str().unwrap_or_default().to_string();
+//!     let mut parts = name.splitn(2, ' ');
+//!     doc["first"] = json!(parts.next().unwrap_or_default());
+//!     doc["last"] = json!(parts.next().unwrap_or_default());
+//!     Ok(doc)
+//! });
+//! assert_eq!(users.get("user:1")?.unwrap().last, "Lovelace");
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::errors::{self, ErrorKind, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::marker::PhantomData;
+
+type Migration = Box<dyn Fn(&KeyValueStoreHostBinding) -> Result<()> + Send + Sync>;
+type Upcast = Box<dyn Fn(Value) -> Result<Value> + Send + Sync>;
+
+const VERSION_FIELD: &str = "schema_version";
+const DOCUMENT_FIELD: &str = "document";
+
+/// Runs ordered migration steps against the store, each exactly once
+pub struct Migrator {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+    steps: Vec<(String, Migration)>,
+}
+
+impl Migrator {
+    /// Creates a migrator recording its progress under keys prefixed with the given name
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str) -> Migrator {
+        Migrator {
+            kv,
+            name: name.to_string(),
+            steps: vec![],
+        }
+    }
+
+    /// Appends a migration step. The first step migrates the store to version 1, the
+    /// second to version 2 and so on, so steps must never be reordered or removed once
+    /// they have been deployed.
+    pub fn with_step<F>(mut self, description: &str, step: F) -> Migrator
+    where
+        F: Fn(&KeyValueStoreHostBinding) -> Result<()> + Send + Sync + 'static,
+    {
+        self.steps.push((description.to_string(), Box::new(step)));
+        self
+    }
+
+    /// The latest schema version known to this migrator
+    pub fn latest_version(&self) -> u32 {
+        self.steps.len() as u32
+    }
+
+    /// The schema version the store has been migrated to
+    pub fn current_version(&self) -> Result<u32> {
+        match self.kv.get(&self.version_key())? {
+            Some(v) => v.parse().map_err(|_| {
+                misc(format!(
+                    "Schema version '{}' recorded by migrator '{}' is not a number",
+                    v, self.name
+                ))
+            }),
+            None => Ok(0),
+        }
+    }
+
+    /// Runs every step beyond the store's current version, in order, returning the version
+    /// reached. Stops early, without error, at a step being run by another instance, and
+    /// returns the error of the first step that fails (or whose version can't be recorded).
+    pub fn run(&self) -> Result<u32> {
+        let mut version = self.current_version()?;
+        while version < self.latest_version() {
+            let next = version + 1;
+            if self.kv.atomic_add(&self.claim_key(next), 1)? != 1 {
+                // another instance is running (or has just run) this step
+                return self.current_version();
+            }
+            let (description, step) = &self.steps[version as usize];
+            log::info!(
+                "Applying migration {} of '{}': {}",
+                next,
+                self.name,
+                description
+            );
+            // a step whose version isn't recorded must be claimable again, or no instance
+            // could ever migrate past it
+            let applied = step(&self.kv)
+                .and_then(|_| self.kv.set(&self.version_key(), &next.to_string(), None));
+            if let Err(e) = applied {
+                self.release(next)?;
+                return Err(e);
+            }
+            version = next;
+        }
+        Ok(version)
+    }
+
+    /// Releases the claim on a step, allowing it to be run again. This is only needed
+    /// after an instance stopped while running the step.
+    pub fn release(&self, version: u32) -> Result<()> {
+        self.kv.del_key(&self.claim_key(version))
+    }
+
+    fn version_key(&self) -> String {
+        format!("{}:version", self.name)
+    }
+
+    fn claim_key(&self, version: u32) -> String {
+        format!("{}:claim:{}", self.name, version)
+    }
+}
+
+/// Reads and writes JSON documents tagged with a schema version, upgrading older documents
+/// to the latest version as they are read
+pub struct Upcaster<T> {
+    kv: KeyValueStoreHostBinding,
+    steps: Vec<Upcast>,
+    write_back: bool,
+    _document: PhantomData<fn() -> T>,
+}
+
+impl<T> Upcaster<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates an upcaster with no steps, for which every document is at the latest version
+    pub fn new(kv: KeyValueStoreHostBinding) -> Upcaster<T> {
+        Upcaster {
+            kv,
+            steps: vec![],
+            write_back: false,
+            _document: PhantomData,
+        }
+    }
+
+    /// Appends a step converting a document from the previous latest version to the next
+    pub fn with_step<F>(mut self, step: F) -> Upcaster<T>
+    where
+        F: Fn(Value) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Stores documents back in the store once they have been upgraded on read, so that
+    /// they are only upgraded once. Any expiry on the document is lost when it is stored.
+    pub fn with_write_back(mut self) -> Upcaster<T> {
+        self.write_back = true;
+        self
+    }
+
+    /// The schema version at which documents are written
+    pub fn latest_version(&self) -> u32 {
+        self.steps.len() as u32
+    }
+
+    /// Obtains a document, upgrading it to the latest version
+    pub fn get(&self, key: &str) -> Result<Option<T>> {
+        let stored: Value = match self.kv.get_json(key)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let (version, mut doc) = split_envelope(stored);
+        if version > self.latest_version() {
+            return Err(misc(format!(
+                "Document '{}' has schema version {}, newer than the latest known version {}",
+                key,
+                version,
+                self.latest_version()
+            )));
+        }
+        for step in &self.steps[version as usize..] {
+            doc = step(doc)?;
+        }
+        let value = serde_json::from_value(doc)?;
+        if self.write_back && version < self.latest_version() {
+            self.set(key, &value, None)?;
+        }
+        Ok(Some(value))
+    }
+
+    /// Stores a document at the latest schema version
+    pub fn set(&self, key: &str, value: &T, expires: Option<u32>) -> Result<()> {
+        let mut envelope = serde_json::Map::new();
+        envelope.insert(VERSION_FIELD.to_string(), self.latest_version().into());
+        envelope.insert(DOCUMENT_FIELD.to_string(), serde_json::to_value(value)?);
+        self.kv.set_json(key, &envelope, expires)
+    }
+}
+
+/// Splits a stored value into its schema version and document, treating anything other
+/// than a version envelope as an unversioned (version 0) document
+fn split_envelope(stored: Value) -> (u32, Value) {
+    match stored {
+        Value::Object(mut map) if map.len() == 2 && map.contains_key(DOCUMENT_FIELD) => {
+            match map.get(VERSION_FIELD).and_then(|v| v.as_u64()) {
+                Some(version) => (version as u32, map.remove(DOCUMENT_FIELD).unwrap()),
+                None => (0, Value::Object(map)),
+            }
+        }
+        other => (0, other),
+    }
+}
+
+fn misc(message: String) -> errors::Error {
+    errors::new(ErrorKind::MiscError(message.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::transport;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use wascc_codec::keyvalue::OP_SET;
+
+    #[test]
+    fn failed_version_write_releases_the_claim() {
+        let provider = MockKeyValueProvider::new();
+        transport::set_transport(provider.clone());
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = runs.clone();
+        let migrator = Migrator::new(crate::keyvalue::default(), "schema").with_step(
+            "count runs",
+            move |_| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            },
+        );
+        provider.fail_next(OP_SET, "schema:version");
+
+        assert!(migrator.run().is_err());
+        assert_eq!(migrator.current_version().unwrap(), 0);
+        assert!(provider.keys().is_empty());
+        assert_eq!(migrator.run().unwrap(), 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,411 @@
+//! # Key-Value Store
+//!
+//! This module contains the key-value store through which guest modules access
+//! the currently bound `wascap:keyvalue` capability provider
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use serde_derive::{Deserialize, Serialize};
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! #[derive(Serialize, Deserialize, Debug, PartialEq)]
+//! struct Settings {
+//!     theme: String,
+//!     volume: u8,
+//! }
+//!
+//! let kv = keyvalue::default();
+//! let settings = Settings { theme: "dark".to_string(), volume: 7 };
+//! kv.set_json("settings", &settings, None)?;
+//! assert_eq!(kv.get_json::<Settings>("settings")?, Some(settings));
+//!
+//! kv.set("settings", "not json", None)?;
+//! assert!(kv.get_json::<Settings>("settings").is_err());
+//!
+//! kv.set_bytes("blob", &[0xde, 0xad, 0xbe, 0xef], None)?;
+//! assert_eq!(kv.get_bytes("blob")?, Some(vec![0xde, 0xad, 0xbe, 0xef]));
+//! kv.set("greeting", "hello", None)?;
+//! assert_eq!(kv.get_bytes("greeting")?, Some(b"hello".to_vec()));
+//!
+//! let tenant = kv.namespace("tenant-42");
+//! tenant.set_add("admins", "alice")?;
+//! tenant.set_add("users", "bob")?;
+//! assert!(kv.exists("tenant-42:admins")?);
+//! assert_eq!(tenant.set_union(vec!["admins".into(), "users".into()])?, vec!["alice", "bob"]);
+//! assert_eq!(tenant.unqualify("tenant-42:admins"), Some("admins"));
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+pub mod backup;
+pub mod cache;
+pub mod collections;
+pub mod index;
+pub mod iter;
+pub mod lease;
+pub mod migrate;
+pub mod probabilistic;
+pub mod queue;
+pub mod ratelimit;
+pub mod search;
+pub mod versioned;
+
+use crate::transport;
+use codec::keyvalue::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use wascc_codec as codec;
+
+use crate::errors::{ErrorKind, Result};
+
+/// The capability ID for the key-value store
+pub const CAPID_KEYVALUE: &str = "wascc:keyvalue";
+
+/// Marks a stored string as base64-encoded binary data written by `set_bytes` and friends
+const BYTES_MARKER: &str = "\u{0}b64:";
+
+/// An abstraction around a host runtime capability for a key-value store
+#[derive(Clone)]
+pub struct KeyValueStoreHostBinding {
+    binding: String,
+    prefix: String,
+}
+
+impl Default for KeyValueStoreHostBinding {
+    fn default() -> Self {
+        host("default")
+    }
+}
+
+/// Creates a named host binding for the key-value store capability
+pub fn host(binding: &str) -> KeyValueStoreHostBinding {
+    KeyValueStoreHostBinding {
+        binding: binding.to_string(),
+        prefix: String::new(),
+    }
+}
+
+/// Creates the default host binding for the key-value store capability
+pub fn default() -> KeyValueStoreHostBinding {
+    KeyValueStoreHostBinding::default()
+}
+
+impl KeyValueStoreHostBinding {
+    /// Returns a view of this binding that prefixes every key with `{namespace}:`. Namespaces
+    /// nest, so `kv.namespace("a").namespace("b")` stores keys under `a:b:`.
+    pub fn namespace(&self, namespace: &str) -> KeyValueStoreHostBinding {
+        KeyValueStoreHostBinding {
+            binding: self.binding.clone(),
+            prefix: format!("{}{}:", self.prefix, namespace),
+        }
+    }
+
+    /// Returns the key as it is stored by the provider, including the namespace prefix
+    pub fn qualify(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Strips this view's namespace prefix from a key as stored by the provider, returning
+    /// `None` if the key does not belong to the namespace
+    pub fn unqualify<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(self.prefix.as_str())
+    }
+
+    /// Obtains a single value from the store
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        let cmd = GetRequest {
+            key: self.qualify(key),
+        };
+        let resp: GetResponse = self.call(OP_GET, cmd)?;
+        if resp.exists {
+            Ok(Some(resp.value))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Sets a value in the store
+    pub fn set(&self, key: &str, value: &str, expires: Option<u32>) -> Result<()> {
+        let cmd = SetRequest {
+            key: self.qualify(key),
+            value: value.to_string(),
+            expires_s: expires.unwrap_or(0) as _,
+        };
+        self.send(OP_SET, cmd).map(|_vec| ())
+    }
+
+    /// Performs an atomic increment operation
+    pub fn atomic_add(&self, key: &str, value: i32) -> Result<i32> {
+        let cmd = AddRequest {
+            key: self.qualify(key),
+            value,
+        };
+        self.call(OP_ADD, cmd).map(|resp: AddResponse| resp.value)
+    }
+
+    /// Adds an item to a list at the given key
+    pub fn list_add(&self, key: &str, item: &str) -> Result<usize> {
+        let cmd = ListPushRequest {
+            key: self.qualify(key),
+            value: item.to_string(),
+        };
+        self.call(OP_PUSH, cmd)
+            .map(|resp: ListResponse| resp.new_count as usize)
+    }
+
+    /// Removes an item from the list at the given key
+    pub fn list_del_item(&self, key: &str, item: &str) -> Result<usize> {
+        let cmd = ListDelItemRequest {
+            key: self.qualify(key),
+            value: item.to_string(),
+        };
+        self.call(OP_LIST_DEL, cmd)
+            .map(|resp: ListResponse| resp.new_count as usize)
+    }
+
+    /// Removes the data associated with a given key, which can include lists or sets
+    pub fn del_key(&self, key: &str) -> Result<()> {
+        let cmd = DelRequest {
+            key: self.qualify(key),
+        };
+        self.send(OP_DEL, cmd).map(|_vec| ())
+    }
+
+    /// Queries a given list-type key for a range of values
+    pub fn list_range(
+        &self,
+        key: &str,
+        start: isize,
+        stop_inclusive: isize,
+    ) -> Result<Vec<String>> {
+        let cmd = ListRangeRequest {
+            key: self.qualify(key),
+            start: start as i32,
+            stop: stop_inclusive as i32,
+        };
+        self.call(OP_RANGE, cmd)
+            .map(|resp: ListRangeResponse| resp.values)
+    }
+
+    /// Clears a list while leaving the key intact
+    pub fn list_clear(&self, key: &str) -> Result<()> {
+        let cmd = ListClearRequest {
+            key: self.qualify(key),
+        };
+        self.send(OP_CLEAR, cmd).map(|_vec| ())
+    }
+
+    /// Adds a value to a set at the given key
+    pub fn set_add(&self, key: &str, value: &str) -> Result<usize> {
+        let cmd = SetAddRequest {
+            key: self.qualify(key),
+            value: value.to_string(),
+        };
+        self.call(OP_SET_ADD, cmd)
+            .map(|resp: SetOperationResponse| resp.new_count as usize)
+    }
+
+    /// Removes a value from the given set
+    pub fn set_remove(&self, key: &str, value: &str) -> Result<usize> {
+        let cmd = SetRemoveRequest {
+            key: self.qualify(key),
+            value: value.to_string(),
+        };
+        self.call(OP_SET_REMOVE, cmd)
+            .map(|resp: SetOperationResponse| resp.new_count as usize)
+    }
+
+    /// Performs a union of sets specified by the list of keys
+    pub fn set_union(&self, keys: Vec<String>) -> Result<Vec<String>> {
+        let cmd = SetUnionRequest {
+            keys: self.qualify_all(keys),
+        };
+        self.call(OP_SET_UNION, cmd)
+            .map(|resp: SetQueryResponse| resp.values)
+    }
+
+    /// Performs the intersection of sets specified by the given keys
+    pub fn set_intersect(&self, keys: Vec<String>) -> Result<Vec<String>> {
+        let cmd = SetIntersectionRequest {
+            keys: self.qualify_all(keys),
+        };
+        self.call(OP_SET_INTERSECT, cmd)
+            .map(|resp: SetQueryResponse| resp.values)
+    }
+
+    /// Returns a list of members belonging to a given set
+    pub fn set_members(&self, key: &str) -> Result<Vec<String>> {
+        let cmd = SetQueryRequest {
+            key: self.qualify(key),
+        };
+        self.call(OP_SET_QUERY, cmd)
+            .map(|resp: SetQueryResponse| resp.values)
+    }
+
+    /// Indicates whether a key exists (not that empty lists/sets may return true for their
+    /// existence if they were cleared instead of deleted)
+    pub fn exists(&self, key: &str) -> Result<bool> {
+        let cmd = KeyExistsQuery {
+            key: self.qualify(key),
+        };
+        self.call(OP_KEY_EXISTS, cmd)
+            .map(|resp: GetResponse| resp.exists)
+    }
+
+    /// Returns an iterator over the pages of the list at the given key, retrieving each
+    /// page of up to `page_size` items with a separate host call
+    pub fn list_pages(&self, key: &str, page_size: usize) -> iter::ListPages<'_> {
+        iter::ListPages::new(self, key, page_size)
+    }
+
+    /// Returns an iterator over the items of the list at the given key, retrieving them
+    /// `page_size` items at a time
+    pub fn list_iter(&self, key: &str, page_size: usize) -> iter::ListIter<'_> {
+        iter::ListIter::new(self, key, page_size)
+    }
+
+    /// Returns an iterator over the members of the set at the given key
+    pub fn set_iter(&self, key: &str) -> iter::SetIter<'_> {
+        iter::SetIter::new(self, key)
+    }
+
+    /// Obtains a value from the store and deserializes it from JSON
+    pub fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serializes a value as JSON and sets it in the store
+    pub fn set_json<T: Serialize>(&self, key: &str, value: &T, expires: Option<u32>) -> Result<()> {
+        self.set(key, &serde_json::to_string(value)?, expires)
+    }
+
+    /// Serializes an item as JSON and adds it to the list at the given key
+    pub fn list_add_json<T: Serialize>(&self, key: &str, item: &T) -> Result<usize> {
+        self.list_add(key, &serde_json::to_string(item)?)
+    }
+
+    /// Removes an item, serialized as JSON, from the list at the given key. Items are
+    /// compared by their serialized form, so the item must serialize identically to the
+    /// stored value (e.g. struct field order and map ordering must match).
+    pub fn list_del_item_json<T: Serialize>(&self, key: &str, item: &T) -> Result<usize> {
+        self.list_del_item(key, &serde_json::to_string(item)?)
+    }
+
+    /// Queries a given list-type key for a range of values, deserializing each from JSON
+    pub fn list_range_json<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        start: isize,
+        stop_inclusive: isize,
+    ) -> Result<Vec<T>> {
+        from_json_values(self.list_range(key, start, stop_inclusive)?)
+    }
+
+    /// Serializes a value as JSON and adds it to the set at the given key
+    pub fn set_add_json<T: Serialize>(&self, key: &str, value: &T) -> Result<usize> {
+        self.set_add(key, &serde_json::to_string(value)?)
+    }
+
+    /// Removes a value, serialized as JSON, from the given set
+    pub fn set_remove_json<T: Serialize>(&self, key: &str, value: &T) -> Result<usize> {
+        self.set_remove(key, &serde_json::to_string(value)?)
+    }
+
+    /// Returns the members of a given set, deserializing each from JSON
+    pub fn set_members_json<T: DeserializeOwned>(&self, key: &str) -> Result<Vec<T>> {
+        from_json_values(self.set_members(key)?)
+    }
+
+    /// Obtains a binary value from the store. Values written with `set_bytes` are decoded,
+    /// while plain string values are returned as their UTF-8 bytes.
+    pub fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.get(key)? {
+            Some(v) => Ok(Some(decode_bytes(v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets a binary value in the store, encoding it so that it survives the provider's
+    /// string representation
+    pub fn set_bytes(&self, key: &str, value: &[u8], expires: Option<u32>) -> Result<()> {
+        self.set(key, &encode_bytes(value), expires)
+    }
+
+    /// Adds a binary item to a list at the given key
+    pub fn list_add_bytes(&self, key: &str, item: &[u8]) -> Result<usize> {
+        self.list_add(key, &encode_bytes(item))
+    }
+
+    /// Removes a binary item from the list at the given key
+    pub fn list_del_item_bytes(&self, key: &str, item: &[u8]) -> Result<usize> {
+        self.list_del_item(key, &encode_bytes(item))
+    }
+
+    /// Queries a given list-type key for a range of binary values
+    pub fn list_range_bytes(
+        &self,
+        key: &str,
+        start: isize,
+        stop_inclusive: isize,
+    ) -> Result<Vec<Vec<u8>>> {
+        self.list_range(key, start, stop_inclusive)?
+            .into_iter()
+            .map(decode_bytes)
+            .collect()
+    }
+
+    fn send(&self, operation: &str, cmd: impl Serialize) -> Result<Vec<u8>> {
+        transport::send(
+            ErrorKind::KeyValueError,
+            &self.binding,
+            CAPID_KEYVALUE,
+            operation,
+            cmd,
+        )
+    }
+
+    fn call<T: DeserializeOwned>(&self, operation: &str, cmd: impl Serialize) -> Result<T> {
+        transport::call(
+            ErrorKind::KeyValueError,
+            &self.binding,
+            CAPID_KEYVALUE,
+            operation,
+            cmd,
+        )
+    }
+
+    fn qualify_all(&self, keys: Vec<String>) -> Vec<String> {
+        if self.prefix.is_empty() {
+            keys
+        } else {
+            keys.iter().map(|k| self.qualify(k)).collect()
+        }
+    }
+}
+
+fn from_json_values<T: DeserializeOwned>(values: Vec<String>) -> Result<Vec<T>> {
+    values
+        .iter()
+        .map(|v| serde_json::from_str(v).map_err(|e| e.into()))
+        .collect()
+}
+
+/// Binary values that are valid UTF-8 (and not mistakable for encoded data) are stored
+/// as-is, everything else is base64-encoded behind a marker
+fn encode_bytes(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(s) if !s.starts_with(BYTES_MARKER) => s.to_string(),
+        _ => format!("{}{}", BYTES_MARKER, base64::encode(value)),
+    }
+}
+
+fn decode_bytes(value: String) -> Result<Vec<u8>> {
+    match value.strip_prefix(BYTES_MARKER) {
+        Some(encoded) => Ok(base64::decode(encoded)?),
+        None => Ok(value.into_bytes()),
+    }
+}
@@ -0,0 +1,423 @@
+//! # Probabilistic Structures
+//!
+//! A Bloom filter (for approximate membership, e.g. deduplication) and a HyperLogLog (for
+//! approximate distinct counts, e.g. unique visitors) whose bit and register arrays are
+//! persisted in the key-value store. Each array is split into fixed-size chunks stored
+//! under `{name}:chunk:{n}`, so an operation only transfers the chunks it touches (an item
+//! touches one chunk of a HyperLogLog, and at most one chunk per hash function of a Bloom
+//! filter).
+//!
+//! Chunks are written as `keyvalue::versioned` documents, so concurrent updates from
+//! several actor instances are retried rather than lost. A structure must always be opened
+//! with the same parameters. Two structures can be merged if they share every parameter
+//! other than their chunk size.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::probabilistic::{BloomFilter, HyperLogLog};
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let kv = keyvalue::default();
+//! let seen = BloomFilter::for_capacity(kv.clone(), "seen-orders", 1000, 0.01);
+//! assert!(seen.add("order-1")?);
+//! assert!(!seen.add("order-1")?);
+//! assert!(seen.contains("order-1")?);
+//! assert!(!seen.contains("order-2")?);
+//!
+//! let monday = HyperLogLog::new(kv.clone(), "visitors:monday", 12);
+//! let tuesday = HyperLogLog::new(kv, "visitors:tuesday", 12);
+//! for n in 0..1000 {
+//!     monday.add(&format!("visitor-{}", n))?;
+//!     tuesday.add(&format!("visitor-{}", n + 500))?;
+//! }
+//! monday.merge(&tuesday)?;
+//! let estimate = monday.estimate()?;
+//! assert!(estimate > 1400 && estimate < 1600, "estimate was {}", estimate);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::versioned::VersionedStore;
+use super::KeyValueStoreHostBinding;
+use crate::errors::{self, ErrorKind, Result};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+const DEFAULT_CHUNK_BYTES: usize = 4096;
+
+/// A Bloom filter persisted in the key-value store
+pub struct BloomFilter {
+    chunks: Chunks,
+    bits: u64,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// Opens the named filter with the given number of bits and hash functions
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str, bits: u64, hashes: u32) -> BloomFilter {
+        let bits = bits.max(8);
+        BloomFilter {
+            chunks: Chunks::new(kv, name, bits.div_ceil(8) as usize),
+            bits,
+            hashes: hashes.max(1),
+        }
+    }
+
+    /// Opens the named filter sized to hold `items` items with the given false positive
+    /// rate (e.g. `0.01` for 1%)
+    pub fn for_capacity(
+        kv: KeyValueStoreHostBinding,
+        name: &str,
+        items: u64,
+        false_positive_rate: f64,
+    ) -> BloomFilter {
+        let items = items.max(1) as f64;
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-items * rate.ln() / (ln2 * ln2)).ceil();
+        let hashes = (bits / items * ln2).round();
+        BloomFilter::new(kv, name, bits as u64, hashes as u32)
+    }
+
+    /// Sets the number of bytes stored under each key
+    pub fn with_chunk_size(mut self, bytes: usize) -> BloomFilter {
+        self.chunks.chunk_bytes = bytes.max(1);
+        self
+    }
+
+    /// Adds an item, returning `false` if it was (probably) already present
+    pub fn add<T: AsRef<[u8]> + ?Sized>(&self, item: &T) -> Result<bool> {
+        let mut added = false;
+        for (chunk, offsets) in self.positions(item.as_ref()) {
+            let current = self.chunks.read(chunk)?;
+            if offsets.iter().all(|bit| is_set(&current, *bit)) {
+                continue;
+            }
+            added |= self.chunks.update(chunk, |bytes| {
+                let mut changed = false;
+                for bit in &offsets {
+                    changed |= !is_set(bytes, *bit);
+                    bytes[bit / 8] |= 1 << (bit % 8);
+                }
+                changed
+            })?;
+        }
+        Ok(added)
+    }
+
+    /// Indicates whether an item may have been added. False positives are possible, but
+    /// false negatives are not.
+    pub fn contains<T: AsRef<[u8]> + ?Sized>(&self, item: &T) -> Result<bool> {
+        for (chunk, offsets) in self.positions(item.as_ref()) {
+            let bytes = self.chunks.read(chunk)?;
+            if !offsets.iter().all(|bit| is_set(&bytes, *bit)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Adds every item of another filter with the same parameters to this one. The two
+    /// filters may use different chunk sizes.
+    pub fn merge(&self, other: &BloomFilter) -> Result<()> {
+        if self.bits != other.bits || self.hashes != other.hashes {
+            return Err(mismatch(&self.chunks.name, &other.chunks.name));
+        }
+        self.chunks.merge(&other.chunks, |a, b| a | b)
+    }
+
+    /// Removes every item from the filter
+    pub fn clear(&self) -> Result<()> {
+        self.chunks.clear()
+    }
+
+    /// The bit offsets of an item within each chunk they fall in, in chunk order
+    fn positions(&self, item: &[u8]) -> Vec<(usize, Vec<usize>)> {
+        let h1 = hash(item, 0);
+        let h2 = hash(item, h1) | 1;
+        let chunk_bits = (self.chunks.chunk_bytes * 8) as u64;
+        let mut positions: Vec<(usize, Vec<usize>)> = vec![];
+        let mut bits: Vec<u64> = (0..self.hashes as u64)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bits)
+            .collect();
+        bits.sort_unstable();
+        for bit in bits {
+            let chunk = (bit / chunk_bits) as usize;
+            let offset = (bit % chunk_bits) as usize;
+            match positions.last_mut() {
+                Some((c, offsets)) if *c == chunk => offsets.push(offset),
+                _ => positions.push((chunk, vec![offset])),
+            }
+        }
+        positions
+    }
+}
+
+/// A HyperLogLog distinct-value counter persisted in the key-value store
+pub struct HyperLogLog {
+    chunks: Chunks,
+    precision: u8,
+}
+
+impl HyperLogLog {
+    /// Opens the named counter with `2^precision` registers. The precision is clamped to
+    /// between 4 and 16; with precision `p` the standard error is about `1.04 / 2^(p/2)`
+    /// (1.6% for the commonly used precision of 12).
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str, precision: u8) -> HyperLogLog {
+        let precision = precision.clamp(4, 16);
+        HyperLogLog {
+            chunks: Chunks::new(kv, name, 1 << precision),
+            precision,
+        }
+    }
+
+    /// Sets the number of registers stored under each key
+    pub fn with_chunk_size(mut self, registers: usize) -> HyperLogLog {
+        self.chunks.chunk_bytes = registers.max(1);
+        self
+    }
+
+    /// Counts an item, returning `true` if doing so changed the counter's state
+    pub fn add<T: AsRef<[u8]> + ?Sized>(&self, item: &T) -> Result<bool> {
+        let h = hash(item.as_ref(), 0);
+        let p = self.precision as u32;
+        let register = (h >> (64 - p)) as usize;
+        let rank = ((h << p) | (1 << (p - 1))).leading_zeros() as u8 + 1;
+        let chunk = register / self.chunks.chunk_bytes;
+        let offset = register % self.chunks.chunk_bytes;
+        if self.chunks.read(chunk)?[offset] >= rank {
+            return Ok(false);
+        }
+        self.chunks.update(chunk, |registers| {
+            if registers[offset] < rank {
+                registers[offset] = rank;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Estimates the number of distinct items counted
+    pub fn estimate(&self) -> Result<u64> {
+        let m = self.chunks.len as f64;
+        let mut sum = 0.0;
+        let mut zeros = 0;
+        for chunk in 0..self.chunks.count() {
+            for register in self.chunks.read(chunk)? {
+                sum += 2f64.powi(-(register as i32));
+                if register == 0 {
+                    zeros += 1;
+                }
+            }
+        }
+        let alpha = match self.chunks.len {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let raw = alpha * m * m / sum;
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            // linear counting is more accurate for small cardinalities
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        Ok(estimate.round() as u64)
+    }
+
+    /// Counts every item counted by another counter with the same precision. The two
+    /// counters may use different chunk sizes.
+    ///
+    /// # Example
+    /// ```
+    /// # use wascc_actor as actor;
+    /// use actor::prelude::*;
+    /// use actor::keyvalue::probabilistic::HyperLogLog;
+    /// # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+    ///
+    /// let kv = keyvalue::default();
+    /// let small = HyperLogLog::new(kv.clone(), "small", 6).with_chunk_size(8);
+    /// let large = HyperLogLog::new(kv.clone(), "large", 6);
+    /// let both = HyperLogLog::new(kv, "both", 6).with_chunk_size(5);
+    /// for n in 0..100 {
+    ///     let item = format!("item-{}", n);
+    ///     if n % 2 == 0 { small.add(&item)?; } else { large.add(&item)?; }
+    ///     both.add(&item)?;
+    /// }
+    /// small.merge(&large)?;
+    /// assert_eq!(small.estimate()?, both.estimate()?);
+    /// # Ok::<(), actor::errors::Error>(())
+    /// ```
+    pub fn merge(&self, other: &HyperLogLog) -> Result<()> {
+        if self.precision != other.precision {
+            return Err(mismatch(&self.chunks.name, &other.chunks.name));
+        }
+        self.chunks.merge(&other.chunks, |a, b| a.max(b))
+    }
+
+    /// Resets the counter
+    pub fn clear(&self) -> Result<()> {
+        self.chunks.clear()
+    }
+}
+
+/// A byte array of fixed length split across versioned documents
+struct Chunks {
+    store: VersionedStore,
+    name: String,
+    len: usize,
+    chunk_bytes: usize,
+}
+
+/// The contents of one chunk, stored as a base64 string
+struct Chunk(Vec<u8>);
+
+impl Chunks {
+    fn new(kv: KeyValueStoreHostBinding, name: &str, len: usize) -> Chunks {
+        Chunks {
+            store: VersionedStore::new(kv),
+            name: name.to_string(),
+            len,
+            chunk_bytes: DEFAULT_CHUNK_BYTES,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.len.div_ceil(self.chunk_bytes)
+    }
+
+    fn chunk_len(&self, chunk: usize) -> usize {
+        self.chunk_bytes.min(self.len - chunk * self.chunk_bytes)
+    }
+
+    fn key(&self, chunk: usize) -> String {
+        format!("{}:chunk:{}", self.name, chunk)
+    }
+
+    fn read(&self, chunk: usize) -> Result<Vec<u8>> {
+        let len = self.chunk_len(chunk);
+        let mut bytes = match self.store.get::<Chunk>(&self.key(chunk))? {
+            Some(doc) => doc.value.0,
+            None => vec![],
+        };
+        bytes.resize(len, 0);
+        Ok(bytes)
+    }
+
+    /// Applies `f` to the chunk's bytes and stores the result as a new version, returning
+    /// whether `f` reported a change
+    fn update<F>(&self, chunk: usize, mut f: F) -> Result<bool>
+    where
+        F: FnMut(&mut [u8]) -> bool,
+    {
+        let len = self.chunk_len(chunk);
+        let mut changed = false;
+        self.store.update(&self.key(chunk), |old: Option<Chunk>| {
+            let mut bytes = old.map(|c| c.0).unwrap_or_default();
+            bytes.resize(len, 0);
+            changed = f(&mut bytes);
+            Chunk(bytes)
+        })?;
+        Ok(changed)
+    }
+
+    fn merge<F>(&self, other: &Chunks, combine: F) -> Result<()>
+    where
+        F: Fn(u8, u8) -> u8,
+    {
+        if self.len != other.len {
+            return Err(mismatch(&self.name, &other.name));
+        }
+        let mut cached: Option<(usize, Vec<u8>)> = None;
+        for chunk in 0..self.count() {
+            // the other side may be split into chunks of a different size, so read the same
+            // byte offsets from whichever of its chunks hold them
+            let start = chunk * self.chunk_bytes;
+            let end = start + self.chunk_len(chunk);
+            let mut theirs = Vec::with_capacity(end - start);
+            let mut offset = start;
+            while offset < end {
+                let n = offset / other.chunk_bytes;
+                if cached.as_ref().map(|(c, _)| *c) != Some(n) {
+                    cached = Some((n, other.read(n)?));
+                }
+                let bytes = &cached.as_ref().unwrap().1;
+                let base = n * other.chunk_bytes;
+                let stop = end.min(base + bytes.len());
+                theirs.extend_from_slice(&bytes[offset - base..stop - base]);
+                offset = stop;
+            }
+            if theirs.iter().all(|b| *b == 0) {
+                continue;
+            }
+            self.update(chunk, |bytes| {
+                for (mine, theirs) in bytes.iter_mut().zip(theirs.iter()) {
+                    *mine = combine(*mine, *theirs);
+                }
+                true
+            })?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        // chunks are overwritten rather than deleted, as deleting a versioned document
+        // would restart its version numbering
+        for chunk in 0..self.count() {
+            if self.store.get::<Chunk>(&self.key(chunk))?.is_some() {
+                self.update(chunk, |bytes| {
+                    bytes.iter_mut().for_each(|b| *b = 0);
+                    true
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Chunk {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Chunk, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(&encoded)
+            .map(Chunk)
+            .map_err(de::Error::custom)
+    }
+}
+
+fn is_set(bytes: &[u8], bit: usize) -> bool {
+    bytes[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn mismatch(name: &str, other: &str) -> errors::Error {
+    errors::new(ErrorKind::MiscError(
+        format!(
+            "Cannot merge '{}' into '{}': parameters differ",
+            other, name
+        )
+        .into(),
+    ))
+}
+
+/// A 64-bit FNV-1a hash with a MurmurHash3 finalizer, which (unlike the standard library's
+/// hasher) is guaranteed to be stable across platforms and compiler versions
+fn hash(data: &[u8], seed: u64) -> u64 {
+    let mut h = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in data {
+        h ^= *byte as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^ (h >> 33)
+}
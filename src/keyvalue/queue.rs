@@ -0,0 +1,424 @@
+//! # Work Queues
+//!
+//! A `Queue<T>` distributes JSON-encoded work items between actor instances using three
+//! key-value lists: items waiting to be delivered, items delivered but not yet acknowledged
+//! (in flight), and items that failed too many times (dead letters). Each delivery carries
+//! a visibility timeout; an item that is neither acknowledged nor released before its
+//! timeout elapses is returned to the queue with its attempt count incremented, and moved
+//! to the dead-letter list once it reaches the maximum number of attempts.
+//!
+//! The key-value capability has no atomic pop, so exclusive delivery is arbitrated with
+//! `atomic_add` on a claim key per item and attempt. Delivery is at-least-once: an item is
+//! written to its new list before being removed from its old one, so an actor that fails
+//! between the two steps causes a duplicate rather than a lost item. Timeouts are measured
+//! with the `clock` module. Items that can't be decoded are moved to the dead-letter list
+//! when they reach the front of the queue (or are found in flight), so they never block the
+//! items behind them.
+//!
+//! Finding the items whose timeout has elapsed means reading the whole in-flight list, so
+//! `dequeue` only does so once per sweep interval (5 seconds by default) across all
+//! consumers. Timed-out items may therefore wait up to that long before being redelivered.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::queue::Queue;
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! clock::set_time_source(clock.clone());
+//! transport::set_transport(provider);
+//!
+//! let queue: Queue<String> = Queue::new(keyvalue::default(), "thumbnails")
+//!     .with_visibility_timeout(30)
+//!     .with_max_attempts(2);
+//! queue.enqueue(&"cat.png".to_string())?;
+//! queue.enqueue(&"dog.png".to_string())?;
+//!
+//! let batch = queue.dequeue(10)?;
+//! assert_eq!(batch.len(), 2);
+//! assert!(queue.ack(&batch[0])?);
+//! queue.release(&batch[1])?;
+//!
+//! // the released item is redelivered, then times out on its second attempt
+//! let retry = queue.dequeue(10)?;
+//! assert_eq!((retry[0].payload.as_str(), retry[0].attempts), ("dog.png", 1));
+//! clock.advance(31);
+//! assert!(queue.dequeue(10)?.is_empty());
+//! assert!(!queue.ack(&retry[0])?);
+//! assert_eq!(queue.dead_letters()?[0].payload, "dog.png");
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::clock;
+use crate::errors::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+const DEFAULT_VISIBILITY_TIMEOUT: u32 = 30;
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_SWEEP_INTERVAL: u32 = 5;
+/// How long the record that an in-flight item was settled is kept, in seconds
+const SETTLED_TTL: u32 = 86_400;
+
+/// A work item as stored in the queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message<T> {
+    /// An identifier assigned to the item when it was enqueued
+    pub id: String,
+    /// The number of previous deliveries that were released or timed out
+    pub attempts: u32,
+    pub payload: T,
+}
+
+/// A work item delivered to a consumer, which must acknowledge or release it before the
+/// visibility timeout elapses
+#[derive(Debug, Clone)]
+pub struct Delivery<T> {
+    pub id: String,
+    pub attempts: u32,
+    pub payload: T,
+    receipt: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct InFlight {
+    deadline: u64,
+    message: Message<serde_json::Value>,
+}
+
+/// A queue of JSON-encoded work items stored in key-value lists
+pub struct Queue<T> {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+    visibility_timeout: u32,
+    max_attempts: u32,
+    sweep_interval: u32,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> Queue<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Creates a handle to the named queue
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str) -> Queue<T> {
+        Queue {
+            kv,
+            name: name.to_string(),
+            visibility_timeout: DEFAULT_VISIBILITY_TIMEOUT,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            sweep_interval: DEFAULT_SWEEP_INTERVAL,
+            _item: PhantomData,
+        }
+    }
+
+    /// Sets how long, in seconds, a delivered item stays invisible to other consumers
+    pub fn with_visibility_timeout(mut self, secs: u32) -> Queue<T> {
+        self.visibility_timeout = secs.max(1);
+        self
+    }
+
+    /// Sets the number of deliveries after which a failing item is dead-lettered
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Queue<T> {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets how often, in seconds, `dequeue` looks for in-flight items whose visibility
+    /// timeout has elapsed
+    pub fn with_sweep_interval(mut self, secs: u32) -> Queue<T> {
+        self.sweep_interval = secs.max(1);
+        self
+    }
+
+    /// Adds an item to the back of the queue, returning its ID
+    pub fn enqueue(&self, item: &T) -> Result<String> {
+        let seq = self.kv.atomic_add(&self.key("seq"), 1)?;
+        let message = Message {
+            id: seq.to_string(),
+            attempts: 0,
+            payload: item,
+        };
+        self.kv
+            .list_add(&self.key("ready"), &serde_json::to_string(&message)?)?;
+        Ok(message.id)
+    }
+
+    /// Delivers up to `max` items from the front of the queue. Items whose visibility
+    /// timeout has elapsed are returned to the queue first, if the sweep interval has passed
+    /// since they were last looked for. Items that can't be decoded are moved straight to
+    /// the dead-letter list, and if a provider error interrupts the batch, the items
+    /// delivered before it are still returned.
+    pub fn dequeue(&self, max: usize) -> Result<Vec<Delivery<T>>> {
+        if max == 0 {
+            return Ok(vec![]);
+        }
+        // the marker's expiry spaces out the sweeps of every consumer of the queue
+        let sweep = self.key("sweep");
+        if !self.kv.exists(&sweep)? {
+            self.kv.set(&sweep, "1", Some(self.sweep_interval))?;
+            self.requeue_expired()?;
+        }
+        let deadline = clock::now()? + self.visibility_timeout as u64;
+        let mut deliveries = vec![];
+        for raw in self
+            .kv
+            .list_range(&self.key("ready"), 0, max as isize - 1)?
+        {
+            match self.deliver(&raw, deadline) {
+                Ok(Some(delivery)) => deliveries.push(delivery),
+                Ok(None) => {}
+                // the items already delivered are in flight, and would only time out if the
+                // error were returned in their place
+                Err(e) if !deliveries.is_empty() => {
+                    log::warn!("Failed to dequeue from '{}': {}", self.name, e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(deliveries)
+    }
+
+    /// Acknowledges successful processing of a delivery, removing it from the queue.
+    /// Returns `false` if the visibility timeout had already elapsed and the item was
+    /// returned to the queue (or dead-lettered).
+    pub fn ack(&self, delivery: &Delivery<T>) -> Result<bool> {
+        if !self.settle(&delivery.id, delivery.attempts)? {
+            return Ok(false);
+        }
+        self.kv
+            .list_del_item(&self.key("inflight"), &delivery.receipt)?;
+        Ok(true)
+    }
+
+    /// Reports that a delivery could not be processed, immediately returning it to the
+    /// queue (or dead-lettering it) rather than waiting for its visibility timeout.
+    /// Returns `false` if the timeout had already elapsed.
+    pub fn release(&self, delivery: &Delivery<T>) -> Result<bool> {
+        if !self.settle(&delivery.id, delivery.attempts)? {
+            return Ok(false);
+        }
+        let record: InFlight = serde_json::from_str(&delivery.receipt)?;
+        self.retry(record.message, &delivery.receipt)?;
+        Ok(true)
+    }
+
+    /// Returns every in-flight item whose visibility timeout has elapsed to the queue (or
+    /// the dead-letter list), returning the number of items affected. In-flight entries
+    /// that can't be decoded are dead-lettered. This reads the whole in-flight list;
+    /// `dequeue` calls it at most once per sweep interval.
+    pub fn requeue_expired(&self) -> Result<usize> {
+        let now = clock::now()?;
+        let mut count = 0;
+        for raw in self.kv.list_range(&self.key("inflight"), 0, -1)? {
+            let record: InFlight = match serde_json::from_str(&raw) {
+                Ok(record) => record,
+                Err(_) => {
+                    self.reject("inflight", &raw)?;
+                    count += 1;
+                    continue;
+                }
+            };
+            if record.deadline > now || !self.settle(&record.message.id, record.message.attempts)? {
+                continue;
+            }
+            self.retry(record.message, &raw)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns the items that exhausted their delivery attempts. Entries that couldn't be
+    /// decoded are omitted; they can be inspected with `dead_letters_raw`.
+    pub fn dead_letters(&self) -> Result<Vec<Message<T>>> {
+        Ok(self
+            .dead_letters_raw()?
+            .iter()
+            .filter_map(|raw| serde_json::from_str(raw).ok())
+            .collect())
+    }
+
+    /// Returns the entries of the dead-letter list as stored, including any that couldn't
+    /// be decoded
+    pub fn dead_letters_raw(&self) -> Result<Vec<String>> {
+        self.kv.list_range(&self.key("dead"), 0, -1)
+    }
+
+    /// Removes all items from the dead-letter list
+    pub fn clear_dead_letters(&self) -> Result<()> {
+        self.kv.del_key(&self.key("dead"))
+    }
+
+    /// Claims and delivers a single entry of the ready list, returning `None` if another
+    /// consumer claimed it first or it couldn't be decoded
+    fn deliver(&self, raw: &str, deadline: u64) -> Result<Option<Delivery<T>>> {
+        let message: Message<serde_json::Value> = match serde_json::from_str(raw) {
+            Ok(message) => message,
+            Err(_) => {
+                self.reject("ready", raw)?;
+                return Ok(None);
+            }
+        };
+        let claim = self.key(&format!("claim:{}:{}", message.id, message.attempts));
+        if self.kv.atomic_add(&claim, 1)? != 1 {
+            return Ok(None);
+        }
+        // the claim has no expiry until it is set below, so give it up on any failure rather
+        // than leave the item in the ready list where every consumer would skip it
+        match self.deliver_claimed(raw, &claim, message, deadline) {
+            Err(e) => {
+                let _ = self.kv.del_key(&claim);
+                Err(e)
+            }
+            delivered => delivered,
+        }
+    }
+
+    fn deliver_claimed(
+        &self,
+        raw: &str,
+        claim: &str,
+        message: Message<serde_json::Value>,
+        deadline: u64,
+    ) -> Result<Option<Delivery<T>>> {
+        // an actor that stops before moving the item leaves the claim to expire, after
+        // which the item is delivered again
+        self.kv.set(claim, "1", Some(self.visibility_timeout))?;
+        let payload = match serde_json::from_value(message.payload.clone()) {
+            Ok(payload) => payload,
+            Err(_) => {
+                self.reject("ready", raw)?;
+                return Ok(None);
+            }
+        };
+        let receipt = serde_json::to_string(&InFlight {
+            deadline,
+            message: message.clone(),
+        })?;
+        self.kv.list_add(&self.key("inflight"), &receipt)?;
+        self.kv.list_del_item(&self.key("ready"), raw)?;
+        Ok(Some(Delivery {
+            id: message.id,
+            attempts: message.attempts,
+            payload,
+            receipt,
+        }))
+    }
+
+    /// Moves an entry of the given list that can't be decoded to the dead-letter list
+    fn reject(&self, list: &str, raw: &str) -> Result<()> {
+        log::warn!(
+            "Dead-lettering undecodable item in '{}': {}",
+            self.name,
+            raw
+        );
+        self.kv.list_add(&self.key("dead"), raw)?;
+        self.kv.list_del_item(&self.key(list), raw)?;
+        Ok(())
+    }
+
+    /// Claims the right to settle an in-flight item, so that an acknowledgement, a release
+    /// and a timeout can't all act on the same delivery
+    fn settle(&self, id: &str, attempts: u32) -> Result<bool> {
+        let key = self.key(&format!("settled:{}:{}", id, attempts));
+        if self.kv.atomic_add(&key, 1)? != 1 {
+            return Ok(false);
+        }
+        // the settlement has been won even if its expiry can't be set, and failing here
+        // would leave the item in flight with nobody able to settle it
+        let _ = self.kv.set(&key, "1", Some(SETTLED_TTL));
+        Ok(true)
+    }
+
+    fn retry(&self, mut message: Message<serde_json::Value>, receipt: &str) -> Result<()> {
+        message.attempts += 1;
+        let target = if message.attempts >= self.max_attempts {
+            "dead"
+        } else {
+            "ready"
+        };
+        self.kv
+            .list_add(&self.key(target), &serde_json::to_string(&message)?)?;
+        self.kv.list_del_item(&self.key("inflight"), receipt)?;
+        Ok(())
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("{}:{}", self.name, suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::testing::MockClock;
+    use crate::transport;
+
+    fn setup() -> (MockClock, KeyValueStoreHostBinding) {
+        let provider = MockKeyValueProvider::new();
+        let clock = provider.clock();
+        clock::set_time_source(clock.clone());
+        transport::set_transport(provider);
+        (clock, crate::keyvalue::default())
+    }
+
+    #[test]
+    fn undecodable_ready_items_are_dead_lettered() {
+        let (_, kv) = setup();
+        let numbers: Queue<u32> = Queue::new(kv.clone(), "numbers");
+        numbers.enqueue(&1).unwrap();
+        Queue::<String>::new(kv.clone(), "numbers")
+            .enqueue(&"two".to_string())
+            .unwrap();
+        kv.list_add("numbers:ready", "not json").unwrap();
+
+        let batch = numbers.dequeue(10).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].payload, 1);
+        assert_eq!(numbers.dead_letters_raw().unwrap().len(), 2);
+        assert!(numbers.dead_letters().unwrap().is_empty());
+        assert!(kv.list_range("numbers:ready", 0, -1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn undecodable_inflight_items_are_dead_lettered() {
+        let (clock, kv) = setup();
+        let numbers: Queue<u32> = Queue::new(kv.clone(), "numbers").with_visibility_timeout(10);
+        numbers.enqueue(&1).unwrap();
+        assert_eq!(numbers.dequeue(10).unwrap().len(), 1);
+        kv.list_add("numbers:inflight", "not json").unwrap();
+        numbers.enqueue(&2).unwrap();
+
+        clock.advance(11);
+        let batch = numbers.dequeue(10).unwrap();
+        assert_eq!(
+            batch.iter().map(|d| d.payload).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        assert_eq!(numbers.dead_letters_raw().unwrap(), vec!["not json"]);
+        assert_eq!(kv.list_range("numbers:inflight", 0, -1).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn timed_out_items_are_looked_for_once_per_sweep_interval() {
+        let (clock, kv) = setup();
+        let numbers: Queue<u32> = Queue::new(kv.clone(), "numbers")
+            .with_visibility_timeout(10)
+            .with_sweep_interval(30);
+        numbers.enqueue(&1).unwrap();
+        assert_eq!(numbers.dequeue(10).unwrap().len(), 1);
+
+        clock.advance(11);
+        assert!(numbers.dequeue(10).unwrap().is_empty());
+        clock.advance(19);
+        assert_eq!(numbers.dequeue(10).unwrap()[0].attempts, 1);
+    }
+}
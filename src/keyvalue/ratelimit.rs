@@ -0,0 +1,366 @@
+//! # Rate Limiting
+//!
+//! Fixed-window and sliding-window rate limiters whose counters live in the key-value
+//! store, so that every instance of an actor shares the same quota. Each window has its
+//! own counter, incremented with `atomic_add` and given an expiry so that stale windows
+//! clean themselves up. The current time comes from the `clock` module.
+//!
+//! The capability can only set an expiry by writing a value, so a counter is created (with
+//! its expiry) before it is first incremented, and never written again: the first request
+//! in a window wins a claim with `atomic_add` and creates the counter, while requests that
+//! arrive before the counter exists re-check for it a few times and are denied, uncounted,
+//! if it still hasn't appeared. Checking for the counter costs an extra read per check.
+//! Concurrent requests therefore can't admit more than `limit` requests in a window, though
+//! a few may be denied at the very start of one. (The exception is a request that stalls
+//! for longer than its claim lasts, a few seconds, while creating the counter.)
+//!
+//! A fixed window admits up to `limit` requests per window, which permits bursts of up to
+//! twice the limit across a window boundary. A sliding window smooths this out by
+//! weighting the previous window's count by how much of it still overlaps the sliding
+//! interval, at the cost of an extra read per check.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::ratelimit::{FixedWindow, SlidingWindow};
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! clock.set(1000);
+//! clock::set_time_source(clock.clone());
+//! transport::set_transport(provider);
+//!
+//! let limiter = FixedWindow::new(keyvalue::default().namespace("ratelimit"), 2, 60);
+//! assert!(limiter.check("api-key-1")?.allowed);
+//! assert_eq!(limiter.check("api-key-1")?.remaining, 0);
+//!
+//! let denied = limiter.check("api-key-1")?;
+//! assert!(!denied.allowed);
+//! assert_eq!(denied.reset_at, 1020);
+//! assert_eq!(denied.too_many_requests().status_code, 429);
+//!
+//! clock.advance(20);
+//! assert!(limiter.check("api-key-1")?.allowed);
+//!
+//! // the previous window's requests still count against a sliding window
+//! let sliding = SlidingWindow::new(keyvalue::default().namespace("sliding"), 2, 60);
+//! assert!(sliding.check("api-key-1")?.allowed);
+//! assert!(sliding.check("api-key-1")?.allowed);
+//! clock.advance(60);
+//! assert!(!sliding.check("api-key-1")?.allowed);
+//! clock.advance(30);
+//! assert!(sliding.check("api-key-1")?.allowed);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::clock;
+use crate::errors::Result;
+use std::collections::HashMap;
+use wascc_codec::http::Response;
+
+/// How many times a request re-checks for a counter being created by another request
+const COUNTER_CHECKS: usize = 3;
+/// How long, in seconds, the claim to create a window's counter is held
+const CLAIM_TTL: u32 = 5;
+
+/// The outcome of a rate limit check
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    /// Whether the request should be admitted
+    pub allowed: bool,
+    /// The maximum number of requests admitted per window
+    pub limit: u32,
+    /// The number of further requests that would currently be admitted
+    pub remaining: u32,
+    /// The time, in seconds since the Unix epoch, at which the current window ends
+    pub reset_at: u64,
+    /// The number of seconds until the current window ends
+    pub retry_after: u64,
+}
+
+impl Decision {
+    /// Builds a `429 Too Many Requests` response carrying `Retry-After` and the
+    /// conventional `X-RateLimit-*` headers
+    pub fn too_many_requests(&self) -> Response {
+        let mut header = HashMap::new();
+        header.insert("Retry-After".to_string(), self.retry_after.to_string());
+        header.insert("X-RateLimit-Limit".to_string(), self.limit.to_string());
+        header.insert(
+            "X-RateLimit-Remaining".to_string(),
+            self.remaining.to_string(),
+        );
+        header.insert("X-RateLimit-Reset".to_string(), self.reset_at.to_string());
+        Response {
+            status_code: 429,
+            status: "Too Many Requests".to_string(),
+            header,
+            body: vec![],
+        }
+    }
+}
+
+/// Admits up to `limit` requests per key in each window of `window` seconds
+#[derive(Clone)]
+pub struct FixedWindow {
+    kv: KeyValueStoreHostBinding,
+    limit: u32,
+    window: u32,
+}
+
+impl FixedWindow {
+    /// Creates a fixed-window limiter storing its counters through the given binding
+    pub fn new(kv: KeyValueStoreHostBinding, limit: u32, window_secs: u32) -> FixedWindow {
+        FixedWindow {
+            kv,
+            limit,
+            window: window_secs.max(1),
+        }
+    }
+
+    /// Counts a request against the given key and decides whether to admit it
+    pub fn check(&self, key: &str) -> Result<Decision> {
+        let now = clock::now()?;
+        let start = window_start(now, self.window);
+        let count = match increment(&self.kv, key, start, self.window)? {
+            Some(count) => count as u64,
+            None => self.limit as u64 + 1,
+        };
+        Ok(decide(self.limit, count, start + self.window as u64, now))
+    }
+}
+
+/// Admits up to `limit` requests per key in any interval of `window` seconds, estimating
+/// the count in the interval from the current and previous fixed windows
+#[derive(Clone)]
+pub struct SlidingWindow {
+    kv: KeyValueStoreHostBinding,
+    limit: u32,
+    window: u32,
+}
+
+impl SlidingWindow {
+    /// Creates a sliding-window limiter storing its counters through the given binding
+    pub fn new(kv: KeyValueStoreHostBinding, limit: u32, window_secs: u32) -> SlidingWindow {
+        SlidingWindow {
+            kv,
+            limit,
+            window: window_secs.max(1),
+        }
+    }
+
+    /// Counts a request against the given key and decides whether to admit it. Denied
+    /// requests are not counted.
+    pub fn check(&self, key: &str) -> Result<Decision> {
+        let now = clock::now()?;
+        let window = self.window as u64;
+        let start = window_start(now, self.window);
+        let previous = match start.checked_sub(window) {
+            Some(prev) => self
+                .kv
+                .get(&counter_key(key, prev))?
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(0),
+            None => 0,
+        };
+        let current = match increment(&self.kv, key, start, self.window)? {
+            Some(count) => count as u64,
+            None => {
+                return Ok(decide(
+                    self.limit,
+                    self.limit as u64 + 1,
+                    start + window,
+                    now,
+                ))
+            }
+        };
+        // the fraction of the previous window still inside the sliding interval
+        let overlap = window - (now - start);
+        let estimate = current + (previous * overlap) / window;
+        let decision = decide(self.limit, estimate, start + window, now);
+        if !decision.allowed {
+            self.kv.atomic_add(&counter_key(key, start), -1)?;
+        }
+        Ok(decision)
+    }
+}
+
+fn window_start(now: u64, window: u32) -> u64 {
+    now - now % window as u64
+}
+
+fn counter_key(key: &str, window_start: u64) -> String {
+    format!("{}:{}", key, window_start)
+}
+
+/// Increments the counter for the window, first creating it with an expiry that outlasts
+/// the following window (which may still need to read it). Returns `None`, without
+/// counting the request, if another request is creating the counter and it doesn't appear.
+fn increment(
+    kv: &KeyValueStoreHostBinding,
+    key: &str,
+    start: u64,
+    window: u32,
+) -> Result<Option<i32>> {
+    let counter = counter_key(key, start);
+    if !kv.exists(&counter)? && !create_counter(kv, &counter, window)? {
+        return Ok(None);
+    }
+    Ok(Some(kv.atomic_add(&counter, 1)?))
+}
+
+/// Creates the counter, or waits for the request that claimed its creation to do so.
+/// Returns whether the counter exists.
+fn create_counter(kv: &KeyValueStoreHostBinding, counter: &str, window: u32) -> Result<bool> {
+    let claim = format!("{}:claim", counter);
+    if kv.atomic_add(&claim, 1)? == 1 {
+        // a previous claimant may have created the counter just before its claim expired
+        let created = kv.set(&claim, "1", Some(CLAIM_TTL)).and_then(|_| {
+            if kv.exists(counter)? {
+                return Ok(());
+            }
+            kv.set(counter, "0", Some(window.saturating_mul(2)))
+        });
+        // give the claim up on failure, so that the next request can create the counter
+        if let Err(e) = created {
+            let _ = kv.del_key(&claim);
+            return Err(e);
+        }
+        return Ok(true);
+    }
+    for _ in 0..COUNTER_CHECKS {
+        if kv.exists(counter)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn decide(limit: u32, count: u64, reset_at: u64, now: u64) -> Decision {
+    Decision {
+        allowed: count <= limit as u64,
+        limit,
+        remaining: (limit as u64).saturating_sub(count) as u32,
+        reset_at,
+        retry_after: reset_at - now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::transport::{self, HostTransport};
+    use crate::HandlerResult;
+    use std::sync::{Arc, Mutex};
+    use wascc_codec::keyvalue::{AddRequest, SetRequest, OP_ADD, OP_SET};
+
+    type Hook = Box<dyn FnOnce() + Send>;
+    type Hooks = Arc<Mutex<Vec<(&'static str, String, Hook)>>>;
+
+    /// Forwards calls to a mock provider, running each hook right after the first request
+    /// with the given operation and key
+    #[derive(Clone)]
+    struct Interleave {
+        provider: MockKeyValueProvider,
+        hooks: Hooks,
+    }
+
+    impl HostTransport for Interleave {
+        fn host_call(
+            &self,
+            binding: &str,
+            capid: &str,
+            operation: &str,
+            msg: &[u8],
+        ) -> HandlerResult<Vec<u8>> {
+            let resp = self.provider.host_call(binding, capid, operation, msg)?;
+            let key = match operation {
+                OP_ADD => wascc_codec::deserialize::<AddRequest>(msg)?.key,
+                OP_SET => wascc_codec::deserialize::<SetRequest>(msg)?.key,
+                _ => return Ok(resp),
+            };
+            let mut hooks = self.hooks.lock().unwrap();
+            if let Some(i) = hooks
+                .iter()
+                .position(|(op, k, _)| *op == operation && *k == key)
+            {
+                let (_, _, f) = hooks.remove(i);
+                drop(hooks);
+                f();
+            }
+            Ok(resp)
+        }
+    }
+
+    /// Arms a hook that makes `count` concurrent checks, recording whether each was allowed
+    fn checks_after(
+        interleave: &Interleave,
+        operation: &'static str,
+        key: &str,
+        count: usize,
+        allowed: &Arc<Mutex<Vec<bool>>>,
+    ) {
+        let allowed = allowed.clone();
+        let hook: Hook = Box::new(move || {
+            let limiter = FixedWindow::new(crate::keyvalue::default(), 3, 60);
+            for _ in 0..count {
+                let decision = limiter.check("client").unwrap();
+                allowed.lock().unwrap().push(decision.allowed);
+            }
+        });
+        interleave
+            .hooks
+            .lock()
+            .unwrap()
+            .push((operation, key.to_string(), hook));
+    }
+
+    #[test]
+    fn increments_during_the_first_hit_are_never_reset() {
+        let provider = MockKeyValueProvider::new();
+        let clock = provider.clock();
+        clock.set(1000);
+        clock::set_time_source(clock);
+        let interleave = Interleave {
+            provider: provider.clone(),
+            hooks: Arc::new(Mutex::new(Vec::new())),
+        };
+        transport::set_transport(interleave.clone());
+        let allowed = Arc::new(Mutex::new(Vec::new()));
+        // while the first request creates the counter, and once it has
+        checks_after(&interleave, OP_ADD, "client:960:claim", 2, &allowed);
+        checks_after(&interleave, OP_SET, "client:960", 3, &allowed);
+
+        let limiter = FixedWindow::new(crate::keyvalue::default(), 3, 60);
+        let first = limiter.check("client").unwrap();
+
+        assert_eq!(
+            *allowed.lock().unwrap(),
+            vec![false, false, true, true, true]
+        );
+        assert!(!first.allowed);
+        let kv = crate::keyvalue::default();
+        assert_eq!(kv.get("client:960").unwrap(), Some("4".to_string()));
+        assert_eq!(provider.ttl("client:960"), Some(120));
+        assert!(!limiter.check("client").unwrap().allowed);
+        transport::clear_transport();
+    }
+
+    #[test]
+    fn failed_creation_gives_up_its_claim() {
+        let provider = MockKeyValueProvider::new();
+        provider.clock().set(1000);
+        clock::set_time_source(provider.clock());
+        transport::set_transport(provider.clone());
+        provider.fail_next(OP_SET, "client:960");
+
+        let limiter = FixedWindow::new(crate::keyvalue::default(), 3, 60);
+        assert!(limiter.check("client").is_err());
+        assert!(provider.keys().is_empty());
+        assert!(limiter.check("client").unwrap().allowed);
+        transport::clear_transport();
+    }
+}
@@ -0,0 +1,131 @@
+//! # Text Search
+//!
+//! A simple inverted index for keyword search. Indexed text is split into terms by
+//! `tokenize`, and each term's postings (the IDs of the documents containing it) are kept
+//! in a set at `{name}:term:{term}`. The terms of each document are recorded in a set at
+//! `{name}:doc:{id}`, so that re-indexing or removing a document can clean up the postings
+//! it no longer appears in. Queries are tokenized the same way, then answered with
+//! `set_intersect` (documents containing every term) or `set_union` (documents containing
+//! any term).
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::search::SearchIndex;
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let catalog = SearchIndex::new(keyvalue::default(), "catalog");
+//! catalog.index("sku-1", "Red cotton T-shirt")?;
+//! catalog.index("sku-2", "Blue cotton socks")?;
+//! catalog.index("sku-3", "Red wool socks")?;
+//!
+//! assert_eq!(catalog.search_all("red socks")?, vec!["sku-3"]);
+//! assert_eq!(catalog.search_any("RED, socks!")?, vec!["sku-1", "sku-2", "sku-3"]);
+//!
+//! catalog.index("sku-3", "Green wool socks")?;
+//! catalog.remove("sku-1")?;
+//! assert!(catalog.search_any("red")?.is_empty());
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::errors::Result;
+use std::collections::BTreeSet;
+
+/// Splits text into lowercase alphanumeric terms, without duplicates
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// An inverted index over documents identified by string IDs
+pub struct SearchIndex {
+    kv: KeyValueStoreHostBinding,
+    name: String,
+}
+
+impl SearchIndex {
+    /// Creates a handle to the index stored under keys prefixed with the given name
+    pub fn new(kv: KeyValueStoreHostBinding, name: &str) -> SearchIndex {
+        SearchIndex {
+            kv,
+            name: name.to_string(),
+        }
+    }
+
+    /// Indexes (or re-indexes) the text of a document, replacing any text previously
+    /// indexed for it
+    pub fn index(&self, id: &str, text: &str) -> Result<()> {
+        let old = self.terms(id)?;
+        let new = tokenize(text);
+        // a term is recorded for the document before its posting is added, and forgotten
+        // after its posting is removed, so a failure part-way through never leaves a
+        // posting that re-indexing or removal wouldn't clean up
+        for term in new.difference(&old) {
+            self.kv.set_add(&self.doc_key(id), term)?;
+            self.kv.set_add(&self.term_key(term), id)?;
+        }
+        for term in old.difference(&new) {
+            self.kv.set_remove(&self.term_key(term), id)?;
+            self.kv.set_remove(&self.doc_key(id), term)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a document and all of its postings from the index
+    pub fn remove(&self, id: &str) -> Result<()> {
+        for term in self.terms(id)? {
+            self.kv.set_remove(&self.term_key(&term), id)?;
+        }
+        self.kv.del_key(&self.doc_key(id))
+    }
+
+    /// Returns the terms indexed for a document
+    pub fn terms(&self, id: &str) -> Result<BTreeSet<String>> {
+        Ok(self
+            .kv
+            .set_members(&self.doc_key(id))?
+            .into_iter()
+            .collect())
+    }
+
+    /// Returns the IDs of the documents containing every term of the query, in sorted
+    /// order. A query without terms matches nothing.
+    pub fn search_all(&self, query: &str) -> Result<Vec<String>> {
+        let keys = self.term_keys(query);
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(sorted(self.kv.set_intersect(keys)?))
+    }
+
+    /// Returns the IDs of the documents containing any term of the query, in sorted order
+    pub fn search_any(&self, query: &str) -> Result<Vec<String>> {
+        let keys = self.term_keys(query);
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(sorted(self.kv.set_union(keys)?))
+    }
+
+    fn term_keys(&self, query: &str) -> Vec<String> {
+        tokenize(query).iter().map(|t| self.term_key(t)).collect()
+    }
+
+    fn term_key(&self, term: &str) -> String {
+        format!("{}:term:{}", self.name, term)
+    }
+
+    fn doc_key(&self, id: &str) -> String {
+        format!("{}:doc:{}", self.name, id)
+    }
+}
+
+fn sorted(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids.dedup();
+    ids
+}
@@ -0,0 +1,205 @@
+//! # Versioned Documents
+//!
+//! The key-value capability has no conditional write, so concurrent read-modify-write
+//! cycles on the same JSON document silently overwrite each other. A `VersionedStore`
+//! stores each document alongside a version number and uses `atomic_add` on a per-version
+//! claim key to decide which writer gets to produce the next version. Writers that lose the
+//! race re-read the document and try again, up to a configurable number of retries, before
+//! failing with `errors::ErrorKind::Conflict`.
+//!
+//! A writer that fails after claiming a version gives the claim up, and claims that remain
+//! expire after a configurable period (an hour by default). A writer that stalls between
+//! reading a document and claiming its next version for longer than that period may
+//! overwrite a newer version, so the claim TTL should comfortably exceed the longest
+//! expected update.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::keyvalue::versioned::VersionedStore;
+//! # actor::transport::set_transport(actor::testing::keyvalue::MockKeyValueProvider::new());
+//!
+//! let docs = VersionedStore::new(keyvalue::default());
+//! let doc = docs.update("visits", |old: Option<Vec<String>>| {
+//!     let mut visits = old.unwrap_or_default();
+//!     visits.push("home".to_string());
+//!     visits
+//! })?;
+//! assert_eq!(doc.version, 1);
+//!
+//! // a writer holding a stale version loses
+//! let err = docs.replace("visits", 0, &vec!["about".to_string()]).unwrap_err();
+//! assert!(matches!(err.kind(), errors::ErrorKind::Conflict(_)));
+//!
+//! docs.replace("visits", 1, &vec!["about".to_string()])?;
+//! assert_eq!(docs.get::<Vec<String>>("visits")?.unwrap().value, vec!["about"]);
+//! # Ok::<(), actor::errors::Error>(())
+//! ```
+
+use super::KeyValueStoreHostBinding;
+use crate::errors::{self, ErrorKind, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_CLAIM_TTL: u32 = 3600;
+
+/// A document together with the version under which it was stored. Documents that have
+/// never been written have version 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    pub version: u64,
+    pub value: T,
+}
+
+/// Stores JSON documents with optimistic concurrency control
+#[derive(Clone)]
+pub struct VersionedStore {
+    kv: KeyValueStoreHostBinding,
+    max_retries: u32,
+    claim_ttl: u32,
+}
+
+impl VersionedStore {
+    /// Creates a versioned document store over the given key-value binding
+    pub fn new(kv: KeyValueStoreHostBinding) -> VersionedStore {
+        VersionedStore {
+            kv,
+            max_retries: DEFAULT_MAX_RETRIES,
+            claim_ttl: DEFAULT_CLAIM_TTL,
+        }
+    }
+
+    /// Sets the number of times `update` re-reads the document and retries after losing a
+    /// race with another writer
+    pub fn with_max_retries(mut self, max_retries: u32) -> VersionedStore {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets how long, in seconds, the claim on a document version is remembered
+    pub fn with_claim_ttl(mut self, claim_ttl: u32) -> VersionedStore {
+        self.claim_ttl = claim_ttl;
+        self
+    }
+
+    /// Obtains a document and its version
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<Versioned<T>>> {
+        self.kv.get_json(key)
+    }
+
+    /// Reads the document, applies `f` to its current value (or `None` if it has never been
+    /// written) and stores the result as the next version. If another writer stores a new
+    /// version first, the document is re-read and `f` is applied again, so `f` may be called
+    /// more than once.
+    pub fn update<T, F>(&self, key: &str, mut f: F) -> Result<Versioned<T>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut(Option<T>) -> T,
+    {
+        for _ in 0..=self.max_retries {
+            let (version, old) = match self.get(key)? {
+                Some(doc) => (doc.version, Some(doc.value)),
+                None => (0, None),
+            };
+            let value = f(old);
+            if let Some(doc) = self.try_write(key, version, value)? {
+                return Ok(doc);
+            }
+        }
+        Err(errors::new(ErrorKind::Conflict(key.to_string())))
+    }
+
+    /// Stores `value` as the successor of `expected_version`, failing with a conflict if the
+    /// document has moved on (or another writer has already claimed the next version)
+    pub fn replace<T: Serialize>(
+        &self,
+        key: &str,
+        expected_version: u64,
+        value: T,
+    ) -> Result<Versioned<T>> {
+        match self.try_write(key, expected_version, value)? {
+            Some(doc) => Ok(doc),
+            None => Err(errors::new(ErrorKind::Conflict(key.to_string()))),
+        }
+    }
+
+    fn try_write<T: Serialize>(
+        &self,
+        key: &str,
+        version: u64,
+        value: T,
+    ) -> Result<Option<Versioned<T>>> {
+        let next = version + 1;
+        let claim = format!("{}:claim:{}", key, next);
+        if self.kv.atomic_add(&claim, 1)? != 1 {
+            return Ok(None);
+        }
+        // the claim has no expiry until it is set below, so give it up on any failure rather
+        // than leave the document unable to move past this version
+        match self.write_claimed(key, &claim, version, value) {
+            Err(e) => {
+                let _ = self.kv.del_key(&claim);
+                Err(e)
+            }
+            written => written,
+        }
+    }
+
+    fn write_claimed<T: Serialize>(
+        &self,
+        key: &str,
+        claim: &str,
+        version: u64,
+        value: T,
+    ) -> Result<Option<Versioned<T>>> {
+        self.kv.set(claim, "1", Some(self.claim_ttl))?;
+        // the claim only guarantees that nobody else writes the next version; make sure the caller
+        // actually read the latest version before writing over it
+        let current = self
+            .kv
+            .get_json::<Versioned<serde_json::Value>>(key)?
+            .map_or(0, |doc| doc.version);
+        if current != version {
+            return Ok(None);
+        }
+        let doc = Versioned {
+            version: version + 1,
+            value,
+        };
+        self.kv.set_json(key, &doc, None)?;
+        Ok(Some(doc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::keyvalue::MockKeyValueProvider;
+    use crate::transport;
+    use wascc_codec::keyvalue::OP_SET;
+
+    fn increment(n: Option<u32>) -> u32 {
+        n.unwrap_or(0) + 1
+    }
+
+    #[test]
+    fn failed_write_gives_up_its_claim() {
+        let provider = MockKeyValueProvider::new();
+        transport::set_transport(provider.clone());
+        let docs = VersionedStore::new(crate::keyvalue::default());
+
+        provider.fail_next(OP_SET, "hits:claim:1");
+        assert!(docs.update("hits", increment).is_err());
+        assert!(provider.keys().is_empty());
+
+        provider.fail_next(OP_SET, "hits");
+        assert!(docs.update("hits", increment).is_err());
+        assert!(provider.keys().is_empty());
+
+        assert_eq!(docs.update("hits", increment).unwrap().version, 1);
+        assert!(provider.ttl("hits:claim:1").is_some());
+    }
+}
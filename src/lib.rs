@@ -36,33 +36,162 @@ pub type HandlerResult<T> = ::std::result::Result<T, Box<dyn std::error::Error +
 
 pub extern crate wapc_guest as wapc;
 
-use wapc_guest::console_log;
+pub use wascc_actor_macros::handler;
 
-/// Actor developers will use this macro to set up their operation handlers
+use transport::console_log;
+
+/// Actor developers will use this macro to set up their operation handlers. Any operation
+/// not listed results in an `errors::ErrorKind::BadDispatch` error naming the operation,
+/// unless a catch-all handler is supplied after a semicolon. The catch-all receives the
+/// operation name and the raw payload, and returns the raw response. An optional
+/// `middleware [...]` clause, placed before the catch-all, registers middleware that will
+/// wrap every dispatch (see the `middleware` module).
+///
+/// # Example
+/// ```
+/// # use wascc_actor as actor;
+/// use actor::prelude::*;
+///
+/// actor_handlers!{
+///     codec::core::OP_HEALTH_REQUEST => health;
+///     _ => fallback
+/// }
+///
+/// fn health(_req: codec::core::HealthRequest) -> HandlerResult<()> {
+///     Ok(())
+/// }
+///
+/// fn fallback(operation: &str, msg: &[u8]) -> HandlerResult<Vec<u8>> {
+///     println(&format!("Proxying {} ({} bytes)", operation, msg.len()));
+///     Ok(msg.to_vec())
+/// }
+/// # let harness = actor::testing::harness::ActorHarness::new(handle_wapc);
+/// # assert_eq!(harness.invoke_raw("Unknown", b"abc").unwrap(), b"abc");
+/// ```
 #[macro_export]
 macro_rules! actor_handlers(
+    { $($key:path => $user_handler:ident),* ; middleware [$($middleware:expr),*] ; _ => $fallback:ident } => {
+        $crate::actor_handlers!(@dispatch [$($key => $user_handler),*] [$($middleware),*] $fallback);
+    };
+    { $($key:path => $user_handler:ident),* ; middleware [$($middleware:expr),*] } => {
+        $crate::actor_handlers!(@dispatch [$($key => $user_handler),*] [$($middleware),*] $crate::bad_dispatch);
+    };
+    { $($key:path => $user_handler:ident),* ; _ => $fallback:ident } => {
+        $crate::actor_handlers!(@dispatch [$($key => $user_handler),*] [] $fallback);
+    };
     { $($key:path => $user_handler:ident),* } => {
+        $crate::actor_handlers!(@dispatch [$($key => $user_handler),*] [] $crate::bad_dispatch);
+    };
+    { @dispatch [$($key:path => $user_handler:ident),*] [$($middleware:expr),*] $fallback:path } => {
         use $crate::wapc::prelude::*;
 
         wapc_handler!(handle_wapc);
         fn handle_wapc(operation: &str, msg: &[u8]) -> CallResult {
-            $crate::logger::ensure_logger();
-            match operation {
+            static MIDDLEWARE: ::std::sync::Once = ::std::sync::Once::new();
+            MIDDLEWARE.call_once(|| { $( $crate::middleware::register($middleware); )* });
+            $crate::dispatch::dispatch(operation, msg, |operation, msg| match operation {
                 $( $key => $user_handler(deserialize(msg)?)
                             .and_then(|r| serialize(r))
                             .map_err(|e| e.into()), )*
-                _ => Err("bad dispatch".into())
-            }
+                _ => $fallback(operation, msg)
+            })
         }
+    };
+);
+
+/// An alternative to `actor_handlers!` for actors whose handlers are spread across
+/// modules. Each handler is a function annotated with `#[handler(OPERATION)]`, and this
+/// macro collects the listed handler paths into the actor's dispatcher. Registering the
+/// same operation more than once is a compile-time error. The optional `middleware [...]`
+/// and `_ => fallback` clauses behave as they do in `actor_handlers!`.
+///
+/// # Example
+/// ```
+/// # use wascc_actor as actor;
+/// use actor::prelude::*;
+///
+/// mod api {
+///     use wascc_actor::prelude::*;
+///
+///     #[handler(codec::http::OP_HANDLE_REQUEST)]
+///     pub fn hello(_req: codec::http::Request) -> HandlerResult<codec::http::Response> {
+///         Ok(codec::http::Response::ok())
+///     }
+/// }
+///
+/// mod ops {
+///     use wascc_actor::prelude::*;
+///
+///     #[handler(codec::core::OP_HEALTH_REQUEST, raw)]
+///     pub fn health(_msg: &[u8]) -> HandlerResult<Vec<u8>> {
+///         Ok(vec![])
+///     }
+/// }
+///
+/// actor_routes!{ api::hello, ops::health }
+/// # let harness = actor::testing::harness::ActorHarness::new(handle_wapc);
+/// # let resp: codec::http::Response = harness.invoke(codec::http::OP_HANDLE_REQUEST, codec::http::Request::default()).unwrap();
+/// # assert_eq!(resp.status_code, 200);
+/// # assert!(harness.invoke_raw(codec::core::OP_HEALTH_REQUEST, &[]).unwrap().is_empty());
+/// ```
+///
+/// ```compile_fail
+/// # use wascc_actor as actor;
+/// use actor::prelude::*;
+///
+/// #[handler(codec::core::OP_HEALTH_REQUEST)]
+/// fn health(_req: codec::core::HealthRequest) -> HandlerResult<()> { Ok(()) }
+///
+/// #[handler(codec::core::OP_HEALTH_REQUEST)]
+/// fn also_health(_req: codec::core::HealthRequest) -> HandlerResult<()> { Ok(()) }
+///
+/// actor_routes!{ health, also_health }
+/// ```
+#[macro_export]
+macro_rules! actor_routes(
+    { $($($segment:ident)::+),* ; middleware [$($middleware:expr),*] ; _ => $fallback:ident } => {
+        $crate::actor_routes!(@dispatch [$($($segment)::+),*] [$($middleware),*] $fallback);
+    };
+    { $($($segment:ident)::+),* ; middleware [$($middleware:expr),*] } => {
+        $crate::actor_routes!(@dispatch [$($($segment)::+),*] [$($middleware),*] $crate::bad_dispatch);
+    };
+    { $($($segment:ident)::+),* ; _ => $fallback:ident } => {
+        $crate::actor_routes!(@dispatch [$($($segment)::+),*] [] $fallback);
+    };
+    { $($($segment:ident)::+),* } => {
+        $crate::actor_routes!(@dispatch [$($($segment)::+),*] [] $crate::bad_dispatch);
+    };
+    { @dispatch [$($($segment:ident)::+),*] [$($middleware:expr),*] $fallback:path } => {
+        use $crate::wapc::prelude::*;
+
+        const ACTOR_ROUTES: &[$crate::dispatch::Route] = &[$( $($segment)::+::ROUTE ),*];
+        const _: () = $crate::dispatch::assert_unique(ACTOR_ROUTES);
 
-     };
+        wapc_handler!(handle_wapc);
+        fn handle_wapc(operation: &str, msg: &[u8]) -> CallResult {
+            static MIDDLEWARE: ::std::sync::Once = ::std::sync::Once::new();
+            MIDDLEWARE.call_once(|| { $( $crate::middleware::register($middleware); )* });
+            $crate::dispatch::dispatch(operation, msg, |operation, msg| {
+                $crate::dispatch::route(ACTOR_ROUTES, operation, msg, $fallback)
+            })
+        }
+    };
 );
 
+/// The default handler for operations not registered with `actor_handlers!`
+#[doc(hidden)]
+pub fn bad_dispatch(operation: &str, _msg: &[u8]) -> HandlerResult<Vec<u8>> {
+    Err(errors::new(errors::ErrorKind::BadDispatch(operation.to_string())).into())
+}
+
 /// Use this function for simple, unstructured logging outside the usual log macros
 pub fn println(msg: &str) {
     console_log(msg)
 }
 
+pub mod clock;
+pub mod context;
+pub mod dispatch;
 pub mod errors;
 pub mod events;
 pub mod extras;
@@ -70,6 +199,10 @@ pub mod http_client;
 pub mod keyvalue;
 pub mod logger;
 pub mod messaging;
+pub mod middleware;
 pub mod objectstore;
 pub mod prelude;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+pub mod transport;
 pub mod untyped;
@@ -1,7 +1,38 @@
+//! # Logging
+//!
+//! This module contains the host binding for the `wascc:logging` capability, which also
+//! backs Rust's `log` macros once an actor has received its first operation. Panics are
+//! reported through the same capability (and the host console) before the module traps.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::testing::harness::ActorHarness;
+//!
+//! actor_handlers!{ codec::core::OP_HEALTH_REQUEST => health }
+//!
+//! fn health(_req: codec::core::HealthRequest) -> HandlerResult<()> {
+//!     panic!("boom");
+//! }
+//!
+//! let harness = ActorHarness::new(handle_wapc);
+//! let req = codec::core::HealthRequest { placeholder: true };
+//! let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+//!     harness.invoke::<_, ()>(codec::core::OP_HEALTH_REQUEST, &req)
+//! }));
+//! assert!(result.is_err());
+//!
+//! let logged: codec::logging::WriteLogRequest =
+//!     harness.calls_to(logger::CAPID_LOGGING)[0].decode().unwrap();
+//! assert_eq!(logged.level, 1);
+//! assert!(logged.body.contains("boom"));
+//! ```
+
+use crate::transport::host_call;
 use crate::HandlerResult;
 use log::{Metadata, Record};
-use std::sync::{Arc, RwLock};
-use wapc_guest::host_call;
+use std::sync::{Arc, Once, RwLock};
 use wascc_codec::logging::*;
 use wascc_codec::serialize;
 
@@ -15,31 +46,75 @@ const DEBUG: u32 = 4;
 const TRACE: u32 = 5;
 
 lazy_static! {
-    static ref CURRENT_BINDING: Arc<RwLock<String>> =
-        { Arc::new(RwLock::new("default".to_string())) };
+    static ref CURRENT_BINDING: Arc<RwLock<String>> = Arc::new(RwLock::new("default".to_string()));
 }
 
 static LOGGER: AutomaticLoggerHostBinding = AutomaticLoggerHostBinding {};
+static PANIC_HOOK: Once = Once::new();
 
+/// Installs the logger used by Rust's `log` macros along with a panic hook that reports
+/// panics (message and location) through `wascc:logging` at the error level and to the
+/// host console before the module traps. The previously installed panic hook runs after
+/// the report. On native targets the hook is shared by the whole process (e.g. every test
+/// in `cargo test`), so there it only reports panics raised while the current thread is
+/// dispatching an operation and leaves all others to the previous hook.
+/// This is called by the dispatcher generated by `actor_handlers!`.
 #[allow(dead_code)]
 #[doc(hidden)]
 pub fn ensure_logger() {
-    match log::set_logger(&LOGGER) {
-        Ok(_) => {}
-        Err(_) => {}
-    }
+    let _ = log::set_logger(&LOGGER);
     log::set_max_level(log::LevelFilter::Trace);
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let context = crate::context::current();
+            if context.is_none() && !cfg!(target_arch = "wasm32") {
+                previous(info);
+                return;
+            }
+            let reason = if let Some(s) = info.payload().downcast_ref::<&str>() {
+                s.to_string()
+            } else if let Some(s) = info.payload().downcast_ref::<String>() {
+                s.to_string()
+            } else {
+                "Box<dyn Any>".to_string()
+            };
+            let location = info
+                .location()
+                .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+                .unwrap_or_else(|| "unknown location".to_string());
+            let operation = context
+                .map(|ctx| format!(" while handling '{}'", ctx.operation()))
+                .unwrap_or_default();
+            report_panic(&format!(
+                "Actor panicked{} at '{}', {}",
+                operation, reason, location
+            ));
+            previous(info);
+        }));
+    });
 }
 
-/// A host binding for the wascc:logging capability
-pub struct AutomaticLoggerHostBinding {}
-
-impl Default for AutomaticLoggerHostBinding {
-    fn default() -> Self {
-        AutomaticLoggerHostBinding {}
+fn report_panic(msg: &str) {
+    // The binding lock may have been poisoned by the panic being reported
+    let binding = match CURRENT_BINDING.read() {
+        Ok(b) => b.to_string(),
+        Err(e) => e.into_inner().to_string(),
+    };
+    let req = WriteLogRequest {
+        level: ERROR,
+        body: msg.to_string(),
+    };
+    if let Ok(payload) = serialize(req) {
+        let _ = host_call(&binding, CAPID_LOGGING, OP_LOG, &payload);
     }
+    crate::println(msg);
 }
 
+/// A host binding for the wascc:logging capability
+#[derive(Default)]
+pub struct AutomaticLoggerHostBinding {}
+
 fn set_binding(binding: &str) {
     *CURRENT_BINDING.write().unwrap() = binding.to_string();
 }
@@ -92,7 +167,7 @@ impl AutomaticLoggerHostBinding {
     /// Write a log entry on the host
     pub fn log(&self, level: u32, body: &str) -> HandlerResult<()> {
         let l = WriteLogRequest {
-            level: level,
+            level,
             body: body.to_string(),
         };
         let _ = host_call(
@@ -179,3 +254,33 @@ impl AutomaticLoggerHostBinding {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{self, HostTransport};
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl HostTransport for Recorder {
+        fn host_call(&self, _: &str, capid: &str, _: &str, _: &[u8]) -> HandlerResult<Vec<u8>> {
+            self.0.lock().unwrap().push(capid.to_string());
+            Ok(vec![])
+        }
+
+        fn console_log(&self, _msg: &str) {}
+    }
+
+    #[test]
+    fn panics_outside_dispatch_are_left_to_the_previous_hook() {
+        let recorder = Recorder::default();
+        transport::set_transport(recorder.clone());
+        ensure_logger();
+        let result = std::panic::catch_unwind(|| panic!("not an actor panic"));
+        assert!(result.is_err());
+        assert!(recorder.0.lock().unwrap().is_empty());
+        transport::clear_transport();
+    }
+}
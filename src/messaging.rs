@@ -3,13 +3,13 @@
 //! This module contains the message broker client interface through which actor modules access
 //! a bound `wascc:messaging` capability provider
 
-use wapc_guest::host_call;
+use crate::transport;
 
 const CAPID_MESSAGING: &str = "wascc:messaging";
 
-use crate::HandlerResult;
+use crate::errors::{ErrorKind, Result};
 use codec::messaging::{BrokerMessage, RequestMessage, OP_PERFORM_REQUEST, OP_PUBLISH_MESSAGE};
-use codec::serialize;
+use serde::Serialize;
 use wascc_codec as codec;
 
 /// Create a new named message broker host binding
@@ -33,35 +33,18 @@ pub struct MessageBrokerHostBinding {
 
 impl MessageBrokerHostBinding {
     /// Publishes a message on a given subject with an optional reply subject
-    pub fn publish(
-        &self,
-        subject: &str,
-        reply_to: Option<&str>,
-        payload: &[u8],
-    ) -> HandlerResult<()> {
+    pub fn publish(&self, subject: &str, reply_to: Option<&str>, payload: &[u8]) -> Result<()> {
         let cmd = BrokerMessage {
             subject: subject.to_string(),
             reply_to: reply_to.map_or("".to_string(), |r| r.to_string()),
             body: payload.to_vec(),
         };
 
-        host_call(
-            &self.binding,
-            CAPID_MESSAGING,
-            OP_PUBLISH_MESSAGE,
-            &serialize(cmd)?,
-        )
-        .map_err(|e| e.into())
-        .map(|_vec| ())
+        self.send(OP_PUBLISH_MESSAGE, cmd).map(|_vec| ())
     }
 
     /// Publishes a message and expects a reply to come back within a given timeout (in milliseconds)
-    pub fn request(
-        &self,
-        subject: &str,
-        payload: &[u8],
-        timeout_ms: u64,
-    ) -> HandlerResult<Vec<u8>> {
+    pub fn request(&self, subject: &str, payload: &[u8], timeout_ms: u64) -> Result<Vec<u8>> {
         let cmd = RequestMessage {
             subject: subject.to_string(),
             timeout_ms: timeout_ms as _,
@@ -70,12 +53,16 @@ impl MessageBrokerHostBinding {
 
         // The broker plugin applies no wrapper around the response from the broker, the
         // raw payload is delivered.
-        host_call(
+        self.send(OP_PERFORM_REQUEST, cmd)
+    }
+
+    fn send(&self, operation: &str, cmd: impl Serialize) -> Result<Vec<u8>> {
+        transport::send(
+            ErrorKind::MessagingError,
             &self.binding,
             CAPID_MESSAGING,
-            OP_PERFORM_REQUEST,
-            &serialize(cmd)?,
+            operation,
+            cmd,
         )
-        .map_err(|e| e.into())
     }
 }
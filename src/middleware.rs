@@ -0,0 +1,117 @@
+//! # Middleware
+//!
+//! Middleware wraps the dispatch performed by `actor_handlers!`, allowing cross-cutting
+//! concerns such as timing, authorization, request logging and error translation to be
+//! written once instead of in every handler. Middleware runs in registration order before
+//! the handler and in reverse order afterward. Any `before` hook can short-circuit dispatch
+//! by returning a response (or an error), in which case the handler is skipped and only the
+//! `after` hooks of the middleware that have already run are invoked.
+//!
+//! Middleware can be registered at runtime with `register`, or declared in the
+//! `actor_handlers!` macro, which registers it once before the first dispatch.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::middleware::Middleware;
+//!
+//! struct RequireBody;
+//!
+//! impl Middleware for RequireBody {
+//!     fn before(&self, _operation: &str, msg: &[u8]) -> HandlerResult<Option<Vec<u8>>> {
+//!         if msg.is_empty() {
+//!             Err("empty payload".into())
+//!         } else {
+//!             Ok(None)
+//!         }
+//!     }
+//! }
+//!
+//! actor_handlers!{
+//!     codec::http::OP_HANDLE_REQUEST => hello;
+//!     middleware [RequireBody]
+//! }
+//!
+//! fn hello(_req: codec::http::Request) -> HandlerResult<codec::http::Response> {
+//!     Ok(codec::http::Response::ok())
+//! }
+//! # let harness = actor::testing::harness::ActorHarness::new(handle_wapc);
+//! # assert!(harness.invoke_raw(codec::http::OP_HANDLE_REQUEST, &[]).is_err());
+//! ```
+
+use crate::HandlerResult;
+use std::sync::{Arc, RwLock};
+
+/// A hook that runs around the dispatch of every operation delivered to the actor
+pub trait Middleware: Send + Sync {
+    /// Invoked before the handler with the operation name and raw payload. Returning
+    /// `Ok(Some(response))` or an error skips the handler and any remaining middleware.
+    fn before(&self, _operation: &str, _msg: &[u8]) -> HandlerResult<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    /// Invoked after the handler (or a short-circuiting middleware) with the result of
+    /// dispatch. The value returned replaces the result seen by outer middleware and the host.
+    fn after(
+        &self,
+        _operation: &str,
+        _msg: &[u8],
+        result: HandlerResult<Vec<u8>>,
+    ) -> HandlerResult<Vec<u8>> {
+        result
+    }
+}
+
+lazy_static! {
+    static ref MIDDLEWARE: Arc<RwLock<Vec<Arc<dyn Middleware>>>> =
+        Arc::new(RwLock::new(Vec::new()));
+}
+
+/// Appends a middleware to the end of the chain
+pub fn register<T>(middleware: T)
+where
+    T: Middleware + 'static,
+{
+    MIDDLEWARE.write().unwrap().push(Arc::new(middleware));
+}
+
+/// Removes all registered middleware
+pub fn clear() {
+    MIDDLEWARE.write().unwrap().clear();
+}
+
+/// Runs the registered middleware chain around the supplied handler. This is invoked
+/// by the dispatcher generated by `actor_handlers!`.
+#[doc(hidden)]
+pub fn run<F>(operation: &str, msg: &[u8], handler: F) -> HandlerResult<Vec<u8>>
+where
+    F: FnOnce(&str, &[u8]) -> HandlerResult<Vec<u8>>,
+{
+    let chain = MIDDLEWARE.read().unwrap().clone();
+    let mut entered = 0;
+    let mut short_circuit = None;
+    for m in chain.iter() {
+        entered += 1;
+        match m.before(operation, msg) {
+            Ok(None) => {}
+            Ok(Some(response)) => {
+                short_circuit = Some(Ok(response));
+                break;
+            }
+            Err(e) => {
+                short_circuit = Some(Err(e));
+                break;
+            }
+        }
+    }
+
+    let result = match short_circuit {
+        Some(result) => result,
+        None => handler(operation, msg),
+    };
+    chain[..entered]
+        .iter()
+        .rev()
+        .fold(result, |result, m| m.after(operation, msg, result))
+}
@@ -1,5 +1,7 @@
-use crate::HandlerResult;
-use wapc_guest::host_call;
+use crate::errors::{ErrorKind, Result};
+use crate::transport;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use wascc_codec::blobstore::Blob;
 use wascc_codec::blobstore::Container;
 use wascc_codec::blobstore::{BlobList, FileChunk, StreamRequest, Transfer};
@@ -7,7 +9,6 @@ use wascc_codec::blobstore::{
     OP_CREATE_CONTAINER, OP_GET_OBJECT_INFO, OP_LIST_OBJECTS, OP_REMOVE_CONTAINER,
     OP_REMOVE_OBJECT, OP_START_DOWNLOAD, OP_START_UPLOAD, OP_UPLOAD_CHUNK,
 };
-use wascc_codec::{deserialize, serialize};
 
 const CAPID_BLOBSTORE: &str = "wascc:blobstore";
 
@@ -38,102 +39,60 @@ pub fn default() -> ObjectStoreHostBinding {
 
 impl ObjectStoreHostBinding {
     /// Creates a new container within the store
-    pub fn create_container(&self, name: &str) -> HandlerResult<Container> {
+    pub fn create_container(&self, name: &str) -> Result<Container> {
         let cmd = Container {
             id: name.to_string(),
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_CREATE_CONTAINER,
-            &serialize(cmd)?,
-        )
-        .map(|v| deserialize::<Container>(v.as_ref()).unwrap())
-        .map_err(|e| e.into())
+        self.call(OP_CREATE_CONTAINER, cmd)
     }
 
     /// Removes a container from the store. Whether or not this will fail if the container
     /// has items may be specific to a given provider implementation.
-    pub fn remove_container(&self, name: &str) -> HandlerResult<()> {
+    pub fn remove_container(&self, name: &str) -> Result<()> {
         let cmd = Container {
             id: name.to_string(),
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_REMOVE_CONTAINER,
-            &serialize(cmd)?,
-        )
-        .map(|_v| ())
-        .map_err(|e| e.into())
+        self.send(OP_REMOVE_CONTAINER, cmd).map(|_v| ())
     }
 
     /// Removes an object from a container
-    pub fn remove_object(&self, name: &str, container: &str) -> crate::HandlerResult<()> {
+    pub fn remove_object(&self, name: &str, container: &str) -> Result<()> {
         let cmd = Blob {
             id: name.to_string(),
             container: container.to_string(),
             byte_size: 0,
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_REMOVE_OBJECT,
-            &serialize(cmd)?,
-        )
-        .map(|_v| ())
-        .map_err(|e| e.into())
+        self.send(OP_REMOVE_OBJECT, cmd).map(|_v| ())
     }
 
     /// Lists all objects within a container
-    pub fn list_objects(&self, container: &str) -> HandlerResult<BlobList> {
+    pub fn list_objects(&self, container: &str) -> Result<BlobList> {
         let cmd = Container {
             id: container.to_string(),
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_LIST_OBJECTS,
-            &serialize(cmd)?,
-        )
-        .map(|v| deserialize::<BlobList>(v.as_ref()).unwrap())
-        .map_err(|e| e.into())
+        self.call(OP_LIST_OBJECTS, cmd)
     }
 
     /// Obtains binary object metadata, does not include the object bytes
-    pub fn get_blob_info(&self, container: &str, id: &str) -> HandlerResult<Option<Blob>> {
+    pub fn get_blob_info(&self, container: &str, id: &str) -> Result<Option<Blob>> {
         let cmd = Blob {
             id: id.to_string(),
             container: container.to_string(),
             byte_size: 0,
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_GET_OBJECT_INFO,
-            &serialize(cmd)?,
-        )
-        .map(|v| {
-            let b = deserialize::<Blob>(v.as_ref()).unwrap();
-            if b.id.is_empty() {
-                None
-            } else {
-                Some(b)
-            }
-        })
-        .map_err(|e| e.into())
+        let b: Blob = self.call(OP_GET_OBJECT_INFO, cmd)?;
+        if b.id.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(b))
+        }
     }
 
     /// Indicates that an upload is about to begin for an item. You should follow this
     /// call up with a for loop/iteration that sends successive chunks to the store. The chunk
     /// size specified in this call is a request or suggestion. It is up to the provider to determine
     /// the actual chunk size, which is returned in the resulting `Transfer` instance
-    pub fn start_upload(
-        &self,
-        blob: &Blob,
-        chunk_size: u64,
-        total_bytes: u64,
-    ) -> HandlerResult<Transfer> {
+    pub fn start_upload(&self, blob: &Blob, chunk_size: u64, total_bytes: u64) -> Result<Transfer> {
         let transfer = Transfer {
             blob_id: blob.id.to_string(),
             container: blob.container.to_string(),
@@ -151,24 +110,12 @@ impl ObjectStoreHostBinding {
             chunk_bytes: vec![],
             context: None,
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_START_UPLOAD,
-            &serialize(cmd)?,
-        )
-        .map(|_v| transfer)
-        .map_err(|e| e.into())
+        self.send(OP_START_UPLOAD, cmd).map(|_v| transfer)
     }
 
     /// Uploads an individual chunk of a file to the blob store. This call must only ever
     /// come after signaling the start of a new upload with the `start_upload` function.
-    pub fn upload_chunk(
-        &self,
-        transfer: &Transfer,
-        offset: u64,
-        bytes: &[u8],
-    ) -> crate::HandlerResult<()> {
+    pub fn upload_chunk(&self, transfer: &Transfer, offset: u64, bytes: &[u8]) -> Result<()> {
         let cmd = FileChunk {
             id: transfer.blob_id.to_string(),
             container: transfer.container.to_string(),
@@ -178,14 +125,7 @@ impl ObjectStoreHostBinding {
             chunk_bytes: bytes.to_vec(),
             context: None,
         };
-        host_call(
-            &self.binding,
-            CAPID_BLOBSTORE,
-            OP_UPLOAD_CHUNK,
-            &serialize(cmd)?,
-        )
-        .map(|_v| ())
-        .map_err(|e| e.into())
+        self.send(OP_UPLOAD_CHUNK, cmd).map(|_v| ())
     }
 
     /// Sends a request to the provider to begin a chunked download of a file. If this
@@ -196,7 +136,7 @@ impl ObjectStoreHostBinding {
         blob: &Blob,
         chunk_size: u64,
         context: Option<String>,
-    ) -> crate::HandlerResult<Transfer> {
+    ) -> Result<Transfer> {
         let transfer = Transfer {
             blob_id: blob.id.to_string(),
             container: blob.container.to_string(),
@@ -211,13 +151,26 @@ impl ObjectStoreHostBinding {
             chunk_size,
             context,
         };
-        host_call(
+        self.send(OP_START_DOWNLOAD, cmd).map(|_v| transfer)
+    }
+
+    fn send(&self, operation: &str, cmd: impl Serialize) -> Result<Vec<u8>> {
+        transport::send(
+            ErrorKind::ObjectStoreError,
+            &self.binding,
+            CAPID_BLOBSTORE,
+            operation,
+            cmd,
+        )
+    }
+
+    fn call<T: DeserializeOwned>(&self, operation: &str, cmd: impl Serialize) -> Result<T> {
+        transport::call(
+            ErrorKind::ObjectStoreError,
             &self.binding,
             CAPID_BLOBSTORE,
-            OP_START_DOWNLOAD,
-            &serialize(cmd)?,
+            operation,
+            cmd,
         )
-        .map(|_v| transfer)
-        .map_err(|e| e.into())
     }
 }
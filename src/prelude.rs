@@ -15,11 +15,16 @@
 //! Glob imports for common actor module development
 
 pub use crate::actor_handlers;
+pub use crate::actor_routes;
+pub use crate::handler;
 pub use crate::println;
 pub use wascc_codec as codec;
 
 pub use crate::errors;
 pub use crate::wapc::prelude::CallResult;
 pub use crate::HandlerResult;
-pub use crate::{events, extras, keyvalue, logger, messaging, objectstore, untyped};
+pub use crate::{
+    clock, context, events, extras, keyvalue, logger, messaging, middleware, objectstore,
+    transport, untyped,
+};
 pub use wascc_codec::{deserialize, serialize};
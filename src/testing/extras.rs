@@ -0,0 +1,97 @@
+//! # Mock Extras Provider
+//!
+//! A deterministic implementation of the `wascc:extras` capability provider. GUIDs and
+//! sequence numbers are generated from a shared counter and random numbers from a seeded
+//! generator, so tests that depend on them produce the same values on every run.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::testing::extras::MockExtrasProvider;
+//!
+//! transport::set_transport(MockExtrasProvider::new());
+//!
+//! let ex = extras::default();
+//! assert_eq!(ex.get_guid().unwrap(), "00000000-0000-4000-8000-000000000001");
+//! assert_eq!(ex.get_sequence_number().unwrap(), 2);
+//! let n = ex.get_random(10, 20).unwrap();
+//! assert!(n >= 10 && n <= 20);
+//! ```
+
+use crate::extras::CAPID_EXTRAS;
+use crate::transport::HostTransport;
+use crate::HandlerResult;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wascc_codec::extras::*;
+use wascc_codec::{deserialize, serialize};
+
+/// A deterministic `wascc:extras` provider suitable for installation as a host transport.
+/// Clones share the same counter and random number generator.
+#[derive(Clone, Default)]
+pub struct MockExtrasProvider {
+    counter: Arc<AtomicU64>,
+    seed: Arc<AtomicU64>,
+}
+
+impl MockExtrasProvider {
+    /// Creates a provider whose counter starts at zero
+    pub fn new() -> MockExtrasProvider {
+        MockExtrasProvider::default()
+    }
+
+    /// Creates a provider whose random numbers are derived from the given seed
+    pub fn with_seed(seed: u64) -> MockExtrasProvider {
+        MockExtrasProvider {
+            counter: Arc::new(AtomicU64::new(0)),
+            seed: Arc::new(AtomicU64::new(seed)),
+        }
+    }
+
+    fn next_random(&self, min: u32, max: u32) -> u32 {
+        // SplitMix64 step
+        let mut z = self
+            .seed
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::SeqCst)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        if max <= min {
+            min
+        } else {
+            min + (z % (u64::from(max - min) + 1)) as u32
+        }
+    }
+}
+
+impl HostTransport for MockExtrasProvider {
+    fn host_call(
+        &self,
+        _binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>> {
+        if capid != CAPID_EXTRAS {
+            return Err(format!("Mock extras provider cannot handle {}", capid).into());
+        }
+        let req: GeneratorRequest = deserialize(msg)?;
+        let mut result = GeneratorResult::default();
+        match operation {
+            OP_REQUEST_GUID => {
+                let n = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+                result.guid = Some(format!("00000000-0000-4000-8000-{:012x}", n));
+            }
+            OP_REQUEST_SEQUENCE => {
+                result.sequence_number = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+            }
+            OP_REQUEST_RANDOM => {
+                result.random_number = self.next_random(req.min, req.max);
+            }
+            _ => return Err(format!("Unsupported extras operation: {}", operation).into()),
+        }
+        serialize(result)
+    }
+}
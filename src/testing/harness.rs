@@ -0,0 +1,169 @@
+//! # Actor Test Harness
+//!
+//! The harness drives the dispatcher generated by `actor_handlers!` natively. It installs
+//! a transport that records every outbound capability call made by the handler, routes
+//! those calls to test doubles registered per capability ID, and decodes the handler's
+//! response. Because the generated `handle_wapc` function is private to the actor module,
+//! tests are expected to live in a child module (or the same file) of the handlers.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::testing::harness::ActorHarness;
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! actor_handlers!{ codec::http::OP_HANDLE_REQUEST => hit_counter }
+//!
+//! fn hit_counter(req: codec::http::Request) -> HandlerResult<codec::http::Response> {
+//!     let hits = keyvalue::default().atomic_add(&req.path, 1)?;
+//!     Ok(codec::http::Response::json(hits, 200, "OK"))
+//! }
+//!
+//! let harness = ActorHarness::new(handle_wapc)
+//!     .with_provider(keyvalue::CAPID_KEYVALUE, MockKeyValueProvider::new());
+//!
+//! let mut req = codec::http::Request::default();
+//! req.path = "/home".to_string();
+//! harness.invoke::<_, codec::http::Response>(codec::http::OP_HANDLE_REQUEST, &req).unwrap();
+//! let resp: codec::http::Response = harness.invoke(codec::http::OP_HANDLE_REQUEST, &req).unwrap();
+//!
+//! assert_eq!(resp.body, b"2");
+//! assert_eq!(harness.calls_to(keyvalue::CAPID_KEYVALUE).len(), 2);
+//! ```
+
+use crate::transport::{self, HostTransport};
+use crate::HandlerResult;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use wascc_codec::{deserialize, serialize};
+
+/// The signature of the dispatcher generated by `actor_handlers!`
+pub type Dispatcher = fn(&str, &[u8]) -> HandlerResult<Vec<u8>>;
+
+/// A record of a single outbound call made by an actor to a capability provider
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostCall {
+    pub binding: String,
+    pub capid: String,
+    pub operation: String,
+    pub payload: Vec<u8>,
+}
+
+impl HostCall {
+    /// Decodes the payload of this call into the given codec request type
+    pub fn decode<T: DeserializeOwned>(&self) -> HandlerResult<T> {
+        deserialize(&self.payload)
+    }
+}
+
+/// Invokes an actor's generated dispatcher natively, capturing capability calls
+pub struct ActorHarness {
+    dispatcher: Dispatcher,
+    providers: Rc<HashMap<String, Box<dyn HostTransport>>>,
+    calls: Arc<Mutex<Vec<HostCall>>>,
+}
+
+impl ActorHarness {
+    /// Creates a new harness around the dispatcher generated by `actor_handlers!`
+    /// (`handle_wapc`)
+    pub fn new(dispatcher: Dispatcher) -> ActorHarness {
+        ActorHarness {
+            dispatcher,
+            providers: Rc::new(HashMap::new()),
+            calls: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Registers a test double that will answer all calls made to the given capability ID.
+    /// Calls to capabilities without a registered provider are recorded and then fail.
+    pub fn with_provider<T>(mut self, capid: &str, provider: T) -> ActorHarness
+    where
+        T: HostTransport + 'static,
+    {
+        Rc::get_mut(&mut self.providers)
+            .expect("providers must be registered before invoking the harness")
+            .insert(capid.to_string(), Box::new(provider));
+        self
+    }
+
+    /// Serializes the request, invokes the dispatcher with the given operation and
+    /// deserializes the handler's response
+    pub fn invoke<Req, Resp>(&self, operation: &str, request: Req) -> HandlerResult<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let raw = self.invoke_raw(operation, &serialize(request)?)?;
+        deserialize(&raw)
+    }
+
+    /// Invokes the dispatcher with a raw payload, returning the raw response
+    pub fn invoke_raw(&self, operation: &str, msg: &[u8]) -> HandlerResult<Vec<u8>> {
+        let recorder = RecordingTransport {
+            providers: self.providers.clone(),
+            calls: self.calls.clone(),
+        };
+        let _restore = RestoreTransport(transport::set_transport(recorder));
+        (self.dispatcher)(operation, msg)
+    }
+
+    /// Returns every capability call made since the harness was created or last cleared
+    pub fn calls(&self) -> Vec<HostCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the capability calls made to the given capability ID
+    pub fn calls_to(&self, capid: &str) -> Vec<HostCall> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| c.capid == capid)
+            .cloned()
+            .collect()
+    }
+
+    /// Discards all recorded capability calls
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+}
+
+/// Reinstates the previous transport when dropped, even if the handler panics
+struct RestoreTransport(Option<Box<dyn HostTransport>>);
+
+impl Drop for RestoreTransport {
+    fn drop(&mut self) {
+        transport::replace_transport(self.0.take());
+    }
+}
+
+struct RecordingTransport {
+    providers: Rc<HashMap<String, Box<dyn HostTransport>>>,
+    calls: Arc<Mutex<Vec<HostCall>>>,
+}
+
+impl HostTransport for RecordingTransport {
+    fn host_call(
+        &self,
+        binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>> {
+        self.calls.lock().unwrap().push(HostCall {
+            binding: binding.to_string(),
+            capid: capid.to_string(),
+            operation: operation.to_string(),
+            payload: msg.to_vec(),
+        });
+        match self.providers.get(capid) {
+            Some(provider) => provider.host_call(binding, capid, operation, msg),
+            None => Err(format!("No test provider registered for {}", capid).into()),
+        }
+    }
+}
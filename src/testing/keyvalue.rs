@@ -0,0 +1,407 @@
+//! # Mock Key-Value Provider
+//!
+//! An in-memory implementation of the `wascc:keyvalue` capability provider. It understands
+//! every operation sent by `keyvalue::KeyValueStoreHostBinding` and answers with the same
+//! codec response types as a real provider, following the semantics of waSCC's in-memory
+//! key-value provider (list and set mutations report the new number of elements). Expiry
+//! is driven by a `MockClock` so that tests can control the passage of time.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::testing::keyvalue::MockKeyValueProvider;
+//!
+//! let provider = MockKeyValueProvider::new();
+//! let clock = provider.clock();
+//! transport::set_transport(provider);
+//!
+//! let kv = keyvalue::default();
+//! assert_eq!(kv.atomic_add("counter", 5).unwrap(), 5);
+//! assert_eq!(kv.atomic_add("counter", -2).unwrap(), 3);
+//!
+//! kv.set("session", "abc", Some(30)).unwrap();
+//! clock.advance(31);
+//! assert_eq!(kv.get("session").unwrap(), None);
+//!
+//! for item in &["a", "b", "c", "d"] {
+//!     kv.list_add("letters", item).unwrap();
+//! }
+//! assert_eq!(kv.list_range("letters", -2, -1).unwrap(), vec!["c", "d"]);
+//!
+//! kv.set_add("left", "x").unwrap();
+//! kv.set_add("left", "y").unwrap();
+//! kv.set_add("right", "y").unwrap();
+//! assert_eq!(kv.set_intersect(vec!["left".into(), "right".into()]).unwrap(), vec!["y"]);
+//! ```
+
+use crate::keyvalue::CAPID_KEYVALUE;
+use crate::testing::MockClock;
+use crate::transport::HostTransport;
+use crate::HandlerResult;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use wascc_codec::keyvalue::*;
+use wascc_codec::{deserialize, serialize};
+
+const WRONG_TYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+enum Value {
+    Atom(String),
+    List(Vec<String>),
+    Set(BTreeSet<String>),
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<u64>,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+}
+
+/// An in-memory `wascc:keyvalue` provider suitable for installation as a host transport.
+/// Clones share the same underlying store and clock.
+#[derive(Clone, Default)]
+pub struct MockKeyValueProvider {
+    store: Arc<RwLock<Store>>,
+    clock: MockClock,
+    failures: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl MockKeyValueProvider {
+    /// Creates an empty provider with a clock starting at zero
+    pub fn new() -> MockKeyValueProvider {
+        MockKeyValueProvider::default()
+    }
+
+    /// Creates an empty provider whose expiry is driven by the supplied clock
+    pub fn with_clock(clock: MockClock) -> MockKeyValueProvider {
+        MockKeyValueProvider {
+            clock,
+            ..MockKeyValueProvider::default()
+        }
+    }
+
+    /// Returns a handle to the clock used to expire keys
+    pub fn clock(&self) -> MockClock {
+        self.clock.clone()
+    }
+
+    /// Returns all live keys currently held in the store, in sorted order
+    pub fn keys(&self) -> Vec<String> {
+        let mut store = self.store.write().unwrap();
+        store.purge_expired(self.clock.now());
+        let mut keys: Vec<_> = store.entries.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Returns the number of seconds remaining before the given key expires, or `None`
+    /// if the key does not exist or has no expiration
+    pub fn ttl(&self, key: &str) -> Option<u64> {
+        let now = self.clock.now();
+        let mut store = self.store.write().unwrap();
+        store.purge_expired(now);
+        store
+            .entries
+            .get(key)
+            .and_then(|e| e.expires_at)
+            .map(|at| at - now)
+    }
+
+    /// Makes the next call of the given operation on the given key fail without taking
+    /// effect, as if the provider had returned an error
+    pub fn fail_next(&self, operation: &str, key: &str) {
+        self.failures
+            .lock()
+            .unwrap()
+            .push((operation.to_string(), key.to_string()));
+    }
+
+    fn injected_failure(&self, operation: &str, msg: &[u8]) -> HandlerResult<()> {
+        let mut failures = self.failures.lock().unwrap();
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let key = request_key(operation, msg)?;
+        match failures
+            .iter()
+            .position(|(op, k)| op == operation && Some(k) == key.as_ref())
+        {
+            Some(i) => {
+                let (op, key) = failures.remove(i);
+                Err(format!("Injected failure of {} on '{}'", op, key).into())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn handle(&self, operation: &str, msg: &[u8]) -> HandlerResult<Vec<u8>> {
+        self.injected_failure(operation, msg)?;
+        let now = self.clock.now();
+        let mut store = self.store.write().unwrap();
+        store.purge_expired(now);
+
+        match operation {
+            OP_GET => {
+                let req: GetRequest = deserialize(msg)?;
+                let resp = match store.entries.get(&req.key) {
+                    Some(Entry {
+                        value: Value::Atom(s),
+                        ..
+                    }) => GetResponse {
+                        value: s.to_string(),
+                        exists: true,
+                    },
+                    Some(_) => return Err(WRONG_TYPE.into()),
+                    None => GetResponse {
+                        value: String::new(),
+                        exists: false,
+                    },
+                };
+                serialize(resp)
+            }
+            OP_SET => {
+                let req: SetRequest = deserialize(msg)?;
+                let expires_at = if req.expires_s > 0 {
+                    Some(now + req.expires_s as u64)
+                } else {
+                    None
+                };
+                store.entries.insert(
+                    req.key,
+                    Entry {
+                        value: Value::Atom(req.value.to_string()),
+                        expires_at,
+                    },
+                );
+                serialize(SetResponse { value: req.value })
+            }
+            OP_ADD => {
+                let req: AddRequest = deserialize(msg)?;
+                let entry = store.entries.entry(req.key).or_insert(Entry {
+                    value: Value::Atom("0".to_string()),
+                    expires_at: None,
+                });
+                let value = match entry.value {
+                    Value::Atom(ref s) => s
+                        .parse::<i32>()
+                        .map_err(|_| "ERR value is not an integer or out of range")?
+                        .checked_add(req.value)
+                        .ok_or("ERR increment or decrement would overflow")?,
+                    _ => return Err(WRONG_TYPE.into()),
+                };
+                entry.value = Value::Atom(value.to_string());
+                serialize(AddResponse { value })
+            }
+            OP_PUSH => {
+                let req: ListPushRequest = deserialize(msg)?;
+                let list = store.list_mut(&req.key, true)?.unwrap();
+                list.push(req.value);
+                serialize(ListResponse {
+                    new_count: list.len() as _,
+                })
+            }
+            OP_LIST_DEL => {
+                let req: ListDelItemRequest = deserialize(msg)?;
+                let new_count = match store.list_mut(&req.key, false)? {
+                    Some(list) => {
+                        list.retain(|v| *v != req.value);
+                        list.len()
+                    }
+                    None => 0,
+                };
+                serialize(ListResponse {
+                    new_count: new_count as _,
+                })
+            }
+            OP_RANGE => {
+                let req: ListRangeRequest = deserialize(msg)?;
+                let values = match store.list_mut(&req.key, false)? {
+                    Some(list) => range(list, req.start, req.stop),
+                    None => vec![],
+                };
+                serialize(ListRangeResponse { values })
+            }
+            OP_CLEAR => {
+                let req: ListClearRequest = deserialize(msg)?;
+                if let Some(list) = store.list_mut(&req.key, false)? {
+                    list.clear();
+                }
+                serialize(DelResponse { key: req.key })
+            }
+            OP_SET_ADD => {
+                let req: SetAddRequest = deserialize(msg)?;
+                let set = store.set_mut(&req.key, true)?.unwrap();
+                set.insert(req.value);
+                serialize(SetOperationResponse {
+                    new_count: set.len() as _,
+                })
+            }
+            OP_SET_REMOVE => {
+                let req: SetRemoveRequest = deserialize(msg)?;
+                let new_count = match store.set_mut(&req.key, false)? {
+                    Some(set) => {
+                        set.remove(&req.value);
+                        set.len()
+                    }
+                    None => 0,
+                };
+                serialize(SetOperationResponse {
+                    new_count: new_count as _,
+                })
+            }
+            OP_SET_UNION => {
+                let req: SetUnionRequest = deserialize(msg)?;
+                let mut values = BTreeSet::new();
+                for key in req.keys.iter() {
+                    values.extend(store.members(key)?);
+                }
+                serialize(SetQueryResponse {
+                    values: values.into_iter().collect(),
+                })
+            }
+            OP_SET_INTERSECT => {
+                let req: SetIntersectionRequest = deserialize(msg)?;
+                let mut values: Option<BTreeSet<String>> = None;
+                for key in req.keys.iter() {
+                    let members = store.members(key)?;
+                    values = Some(match values {
+                        Some(acc) => acc.intersection(&members).cloned().collect(),
+                        None => members,
+                    });
+                }
+                serialize(SetQueryResponse {
+                    values: values.unwrap_or_default().into_iter().collect(),
+                })
+            }
+            OP_SET_QUERY => {
+                let req: SetQueryRequest = deserialize(msg)?;
+                serialize(SetQueryResponse {
+                    values: store.members(&req.key)?.into_iter().collect(),
+                })
+            }
+            OP_KEY_EXISTS => {
+                let req: KeyExistsQuery = deserialize(msg)?;
+                serialize(GetResponse {
+                    value: String::new(),
+                    exists: store.entries.contains_key(&req.key),
+                })
+            }
+            OP_DEL => {
+                let req: DelRequest = deserialize(msg)?;
+                store.entries.remove(&req.key);
+                serialize(DelResponse { key: req.key })
+            }
+            _ => Err(format!("Unsupported key-value operation: {}", operation).into()),
+        }
+    }
+}
+
+impl HostTransport for MockKeyValueProvider {
+    fn host_call(
+        &self,
+        _binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>> {
+        if capid != CAPID_KEYVALUE {
+            return Err(format!("Mock key-value provider cannot handle {}", capid).into());
+        }
+        self.handle(operation, msg)
+    }
+}
+
+impl Store {
+    fn purge_expired(&mut self, now: u64) {
+        self.entries
+            .retain(|_, e| !matches!(e.expires_at, Some(at) if at <= now));
+    }
+
+    fn list_mut(&mut self, key: &str, create: bool) -> HandlerResult<Option<&mut Vec<String>>> {
+        if create && !self.entries.contains_key(key) {
+            self.entries.insert(
+                key.to_string(),
+                Entry {
+                    value: Value::List(vec![]),
+                    expires_at: None,
+                },
+            );
+        }
+        match self.entries.get_mut(key) {
+            Some(Entry {
+                value: Value::List(list),
+                ..
+            }) => Ok(Some(list)),
+            Some(_) => Err(WRONG_TYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    fn set_mut(&mut self, key: &str, create: bool) -> HandlerResult<Option<&mut BTreeSet<String>>> {
+        if create && !self.entries.contains_key(key) {
+            self.entries.insert(
+                key.to_string(),
+                Entry {
+                    value: Value::Set(BTreeSet::new()),
+                    expires_at: None,
+                },
+            );
+        }
+        match self.entries.get_mut(key) {
+            Some(Entry {
+                value: Value::Set(set),
+                ..
+            }) => Ok(Some(set)),
+            Some(_) => Err(WRONG_TYPE.into()),
+            None => Ok(None),
+        }
+    }
+
+    fn members(&mut self, key: &str) -> HandlerResult<BTreeSet<String>> {
+        Ok(self.set_mut(key, false)?.cloned().unwrap_or_default())
+    }
+}
+
+/// Extracts the key targeted by a request, for operations that target a single key
+fn request_key(operation: &str, msg: &[u8]) -> HandlerResult<Option<String>> {
+    Ok(Some(match operation {
+        OP_GET => deserialize::<GetRequest>(msg)?.key,
+        OP_SET => deserialize::<SetRequest>(msg)?.key,
+        OP_ADD => deserialize::<AddRequest>(msg)?.key,
+        OP_PUSH => deserialize::<ListPushRequest>(msg)?.key,
+        OP_LIST_DEL => deserialize::<ListDelItemRequest>(msg)?.key,
+        OP_RANGE => deserialize::<ListRangeRequest>(msg)?.key,
+        OP_CLEAR => deserialize::<ListClearRequest>(msg)?.key,
+        OP_SET_ADD => deserialize::<SetAddRequest>(msg)?.key,
+        OP_SET_REMOVE => deserialize::<SetRemoveRequest>(msg)?.key,
+        OP_SET_QUERY => deserialize::<SetQueryRequest>(msg)?.key,
+        OP_KEY_EXISTS => deserialize::<KeyExistsQuery>(msg)?.key,
+        OP_DEL => deserialize::<DelRequest>(msg)?.key,
+        _ => return Ok(None),
+    }))
+}
+
+/// Resolves an inclusive range using the same rules as Redis' `LRANGE`: negative indices
+/// count back from the end of the list and out of range indices are clamped
+fn range(list: &[String], start: i32, stop: i32) -> Vec<String> {
+    let len = list.len() as i64;
+    let resolve = |i: i32| {
+        let i = i as i64;
+        if i < 0 {
+            len + i
+        } else {
+            i
+        }
+    };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(len - 1);
+    if start > stop || start >= len {
+        return vec![];
+    }
+    list[start as usize..=stop as usize].to_vec()
+}
@@ -0,0 +1,110 @@
+//! # Testing
+//!
+//! Native test doubles for the capability providers an actor talks to. These are only
+//! available when compiling for a non-`wasm32` target, and are intended to be installed
+//! with `transport::set_transport` so that actor logic can be exercised by `cargo test`
+//! without a waSCC host. Use a `MockHost` when the code under test talks to more than one
+//! capability.
+
+use crate::clock::TimeSource;
+use crate::transport::HostTransport;
+use crate::HandlerResult;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub mod extras;
+pub mod harness;
+pub mod keyvalue;
+
+/// A host transport that routes each call to the test double registered for its
+/// capability ID
+///
+/// # Example
+/// ```
+/// # use wascc_actor as actor;
+/// use actor::prelude::*;
+/// use actor::testing::{extras::MockExtrasProvider, keyvalue::MockKeyValueProvider, MockHost};
+///
+/// transport::set_transport(
+///     MockHost::new()
+///         .with_provider(keyvalue::CAPID_KEYVALUE, MockKeyValueProvider::new())
+///         .with_provider(extras::CAPID_EXTRAS, MockExtrasProvider::new()),
+/// );
+///
+/// let id = extras::default().get_guid().unwrap();
+/// keyvalue::default().set("id", &id, None).unwrap();
+/// assert!(messaging::default().publish("subject", None, b"hi").is_err());
+/// ```
+#[derive(Default)]
+pub struct MockHost {
+    providers: HashMap<String, Box<dyn HostTransport>>,
+}
+
+impl MockHost {
+    /// Creates a host with no registered providers
+    pub fn new() -> MockHost {
+        MockHost::default()
+    }
+
+    /// Registers a test double that will answer all calls made to the given capability ID
+    pub fn with_provider<T>(mut self, capid: &str, provider: T) -> MockHost
+    where
+        T: HostTransport + 'static,
+    {
+        self.providers.insert(capid.to_string(), Box::new(provider));
+        self
+    }
+}
+
+impl HostTransport for MockHost {
+    fn host_call(
+        &self,
+        binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>> {
+        match self.providers.get(capid) {
+            Some(provider) => provider.host_call(binding, capid, operation, msg),
+            None => Err(format!("No test provider registered for {}", capid).into()),
+        }
+    }
+}
+
+/// A manually advanced clock, measured in whole seconds, shared between the test and
+/// any test doubles that need a notion of time (e.g. for key expiry)
+#[derive(Clone, Default)]
+pub struct MockClock {
+    now: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a new clock starting at the given number of seconds
+    pub fn new(start_secs: u64) -> MockClock {
+        MockClock {
+            now: Arc::new(AtomicU64::new(start_secs)),
+        }
+    }
+
+    /// Returns the current time of the clock in seconds
+    pub fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    /// Moves the clock forward by the given number of seconds
+    pub fn advance(&self, secs: u64) {
+        self.now.fetch_add(secs, Ordering::SeqCst);
+    }
+
+    /// Sets the clock to an absolute number of seconds
+    pub fn set(&self, secs: u64) {
+        self.now.store(secs, Ordering::SeqCst);
+    }
+}
+
+impl TimeSource for MockClock {
+    fn now(&self) -> u64 {
+        MockClock::now(self)
+    }
+}
@@ -0,0 +1,181 @@
+//! # Host Transport
+//!
+//! Every host binding in this crate sends its requests through the functions in this module
+//! rather than calling waPC directly. When compiled for `wasm32`, calls are delivered to the
+//! waSCC host via `wapc_guest::host_call` unless another transport has been installed. On
+//! native targets there is no host, so a transport must be installed with `set_transport`
+//! before any binding is used. This is what allows actor logic to be exercised with `cargo test`.
+//!
+//! # Example
+//! ```
+//! # use wascc_actor as actor;
+//! use actor::prelude::*;
+//! use actor::transport::{self, HostTransport};
+//!
+//! struct Echo;
+//!
+//! impl HostTransport for Echo {
+//!     fn host_call(&self, _binding: &str, _capid: &str, _op: &str, msg: &[u8]) -> HandlerResult<Vec<u8>> {
+//!         Ok(msg.to_vec())
+//!     }
+//! }
+//!
+//! transport::set_transport(Echo);
+//! let res = untyped::default().call("example:echo", "Echo", b"hello".to_vec()).unwrap();
+//! assert_eq!(res, b"hello");
+//! ```
+
+use crate::errors::{self, ErrorKind, ProviderError, Result};
+use crate::HandlerResult;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+use wascc_codec::{deserialize, serialize};
+
+/// A transport is responsible for delivering a host call made by a binding to a capability
+/// provider and returning the provider's raw response.
+pub trait HostTransport {
+    /// Deliver an operation and its serialized payload to the capability provider identified
+    /// by the capability ID on the named binding
+    fn host_call(
+        &self,
+        binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>>;
+
+    /// Write an unstructured message to the host's console. The default implementation
+    /// writes to standard output.
+    fn console_log(&self, msg: &str) {
+        std::println!("{}", msg);
+    }
+}
+
+/// The transport used by default inside a waSCC host, backed by the waPC guest SDK
+#[cfg(target_arch = "wasm32")]
+pub struct WapcTransport;
+
+#[cfg(target_arch = "wasm32")]
+impl HostTransport for WapcTransport {
+    fn host_call(
+        &self,
+        binding: &str,
+        capid: &str,
+        operation: &str,
+        msg: &[u8],
+    ) -> HandlerResult<Vec<u8>> {
+        wapc_guest::host_call(binding, capid, operation, msg).map_err(|e| e.into())
+    }
+
+    fn console_log(&self, msg: &str) {
+        wapc_guest::console_log(msg)
+    }
+}
+
+thread_local! {
+    static TRANSPORT: RefCell<Option<Box<dyn HostTransport>>> = const { RefCell::new(None) };
+}
+
+/// Installs a transport through which all subsequent host calls on the current thread will
+/// be delivered, returning the previously installed transport (if any)
+pub fn set_transport<T>(transport: T) -> Option<Box<dyn HostTransport>>
+where
+    T: HostTransport + 'static,
+{
+    replace_transport(Some(Box::new(transport)))
+}
+
+/// Removes the installed transport from the current thread, returning it. Inside a waSCC host
+/// this restores the default waPC transport.
+pub fn clear_transport() -> Option<Box<dyn HostTransport>> {
+    replace_transport(None)
+}
+
+/// Replaces the installed transport with the one supplied, returning the previous one. This
+/// is primarily useful for restoring a transport obtained from `set_transport`.
+pub fn replace_transport(
+    transport: Option<Box<dyn HostTransport>>,
+) -> Option<Box<dyn HostTransport>> {
+    TRANSPORT.with(|t| t.replace(transport))
+}
+
+/// Performs a host call through the currently installed transport
+pub fn host_call(
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    msg: &[u8],
+) -> HandlerResult<Vec<u8>> {
+    TRANSPORT.with(|t| match *t.borrow() {
+        Some(ref transport) => transport.host_call(binding, capid, operation, msg),
+        None => default_host_call(binding, capid, operation, msg),
+    })
+}
+
+/// Serializes a command and sends it to a capability provider through the installed
+/// transport, reporting a failed call as an error of the given kind
+pub(crate) fn send(
+    kind: fn(ProviderError) -> ErrorKind,
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    cmd: impl Serialize,
+) -> Result<Vec<u8>> {
+    host_call(binding, capid, operation, &serialize(cmd)?)
+        .map_err(|e| errors::provider_error(kind, binding, capid, operation, e))
+}
+
+/// Sends a command like `send`, then deserializes the provider's response
+pub(crate) fn call<T: DeserializeOwned>(
+    kind: fn(ProviderError) -> ErrorKind,
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    cmd: impl Serialize,
+) -> Result<T> {
+    let vec = send(kind, binding, capid, operation, cmd)?;
+    deserialize(&vec).map_err(|e| errors::decode_error(binding, capid, operation, e))
+}
+
+/// Writes a message to the host console through the currently installed transport
+pub fn console_log(msg: &str) {
+    TRANSPORT.with(|t| match *t.borrow() {
+        Some(ref transport) => transport.console_log(msg),
+        None => default_console_log(msg),
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_host_call(
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    msg: &[u8],
+) -> HandlerResult<Vec<u8>> {
+    WapcTransport.host_call(binding, capid, operation, msg)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_host_call(
+    binding: &str,
+    capid: &str,
+    operation: &str,
+    _msg: &[u8],
+) -> HandlerResult<Vec<u8>> {
+    Err(format!(
+        "No host transport installed for call to {} ({}) on binding '{}'",
+        capid, operation, binding
+    )
+    .into())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_console_log(msg: &str) {
+    WapcTransport.console_log(msg)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn default_console_log(msg: &str) {
+    std::println!("{}", msg);
+}
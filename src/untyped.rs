@@ -3,9 +3,9 @@
 //! This module contains the message broker client interface through which actor modules access
 //! a bound `wascc:messaging` capability provider
 
-use wapc_guest::host_call;
+use crate::transport::host_call;
 
-use crate::HandlerResult;
+use crate::errors::{self, ErrorKind, Result};
 
 /// An untyped (or "raw") host binding. This allows the actor to send arbitary binary
 /// payloads with named operations. This will likely be wrapped by a fit-for-purpose
@@ -30,7 +30,9 @@ pub fn default() -> UntypedHostBinding {
 
 impl UntypedHostBinding {
     /// Invoke the given operation on the target capability ID with the specified payload
-    pub fn call(&self, capid: &str, operation: &str, payload: Vec<u8>) -> HandlerResult<Vec<u8>> {
-        host_call(&self.binding, capid, operation, &payload).map_err(|e| e.into())
+    pub fn call(&self, capid: &str, operation: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+        host_call(&self.binding, capid, operation, &payload).map_err(|e| {
+            errors::provider_error(ErrorKind::HostError, &self.binding, capid, operation, e)
+        })
     }
 }